@@ -0,0 +1,79 @@
+use fast_stream::bytes::{Bytes, ValueRead, ValueWrite};
+use fast_stream::endian::Endian;
+use fast_stream::stream::Stream;
+use std::io::Result;
+
+//已经压缩过的格式，再 deflate 基本没有收益
+pub const DEFAULT_STORE_EXTENSIONS: &[&str] = &[
+    "png", "jpg", "jpeg", "gif", "webp", "heic", "car", "zip", "ipa", "apk", "jar", "gz", "tgz",
+    "bz2", "xz", "7z", "rar", "zst", "mp3", "mp4", "m4a", "m4v", "mov", "aac", "ogg", "webm",
+];
+//抽样的字节数和判定为不可压缩的熵(比特/字节)
+const SAMPLE_SIZE: usize = 64 * 1024;
+const ENTROPY_THRESHOLD: f64 = 7.5;
+
+//自适应压缩：按扩展名或抽样熵跳过压缩，压缩后没有变小也退回 Store
+#[derive(Debug, Clone)]
+pub struct Adaptive {
+    pub store_extensions: Vec<String>,
+}
+impl Default for Adaptive {
+    fn default() -> Self {
+        Adaptive {
+            store_extensions: DEFAULT_STORE_EXTENSIONS
+                .iter()
+                .map(|extension| extension.to_string())
+                .collect(),
+        }
+    }
+}
+impl Adaptive {
+    pub fn sample_size() -> usize {
+        SAMPLE_SIZE
+    }
+    pub fn is_store_extension(&self, file_name: &str) -> bool {
+        let Some((_, extension)) = file_name.rsplit_once('.') else {
+            return false;
+        };
+        self.store_extensions
+            .iter()
+            .any(|store_extension| store_extension.eq_ignore_ascii_case(extension))
+    }
+    //抽样数据的熵接近 8 时认为已经是压缩或加密数据
+    pub fn is_incompressible(sample: &[u8]) -> bool {
+        sample.len() >= 1024 && entropy(sample) > ENTROPY_THRESHOLD
+    }
+}
+//香农熵，单位为比特/字节
+pub fn entropy(data: &[u8]) -> f64 {
+    if data.is_empty() {
+        return 0.0;
+    }
+    let mut counts = [0u64; 256];
+    for byte in data {
+        counts[*byte as usize] += 1;
+    }
+    let total = data.len() as f64;
+    counts
+        .iter()
+        .filter(|count| **count > 0)
+        .map(|count| {
+            let p = *count as f64 / total;
+            -p * p.log2()
+        })
+        .sum()
+}
+impl ValueRead for Adaptive {
+    fn read(stream: &mut Stream) -> Result<Self> {
+        let store_extensions: Vec<String> = stream.read_value()?;
+        Ok(Adaptive { store_extensions })
+    }
+}
+impl ValueWrite for Adaptive {
+    fn write(self, endian: &Endian) -> Result<Stream> {
+        let mut stream = Stream::empty();
+        stream.with_endian(endian.clone());
+        stream.write_value(self.store_extensions)?;
+        Ok(stream)
+    }
+}
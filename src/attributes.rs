@@ -0,0 +1,128 @@
+//external_file_attributes: 高 16 位为 Unix st_mode，低 8 位为 MS-DOS 属性
+
+//Unix 文件类型
+pub const S_IFMT: u32 = 0o170000;
+pub const S_IFREG: u32 = 0o100000;
+pub const S_IFDIR: u32 = 0o040000;
+pub const S_IFLNK: u32 = 0o120000;
+//Unix 权限位
+pub const S_ISUID: u32 = 0o4000;
+pub const S_ISGID: u32 = 0o2000;
+pub const S_ISVTX: u32 = 0o1000;
+pub const S_IRWXU: u32 = 0o700;
+pub const S_IRWXG: u32 = 0o070;
+pub const S_IRWXO: u32 = 0o007;
+pub const PERMISSIONS_MASK: u32 = 0o7777;
+pub const EXECUTABLE_MASK: u32 = 0o111;
+//解压时不恢复 setuid、setgid 和 sticky 位
+pub const EXTRACT_PERMISSIONS_MASK: u32 = S_IRWXU | S_IRWXG | S_IRWXO;
+
+//MS-DOS 属性
+pub const DOS_READ_ONLY: u8 = 0x01;
+pub const DOS_HIDDEN: u8 = 0x02;
+pub const DOS_SYSTEM: u8 = 0x04;
+pub const DOS_DIRECTORY: u8 = 0x10;
+pub const DOS_ARCHIVE: u8 = 0x20;
+
+//version made by 的高字节
+pub const OS_MS_DOS: u8 = 0;
+pub const OS_UNIX: u8 = 3;
+
+//新增条目的默认模式
+pub const DEFAULT_FILE_MODE: u32 = S_IFREG | 0o644;
+pub const DEFAULT_DIR_MODE: u32 = S_IFDIR | 0o755;
+pub const DEFAULT_SYMLINK_MODE: u32 = S_IFLNK | 0o777;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileType {
+    Regular,
+    Directory,
+    Symlink,
+}
+impl FileType {
+    pub fn from_mode(mode: u32) -> Option<Self> {
+        match mode & S_IFMT {
+            S_IFREG => Some(FileType::Regular),
+            S_IFDIR => Some(FileType::Directory),
+            S_IFLNK => Some(FileType::Symlink),
+            _ => None,
+        }
+    }
+    pub fn mode_bits(&self) -> u32 {
+        match self {
+            FileType::Regular => S_IFREG,
+            FileType::Directory => S_IFDIR,
+            FileType::Symlink => S_IFLNK,
+        }
+    }
+}
+
+pub fn external_attributes(mode: u32, dos_attributes: u8) -> u32 {
+    (mode << 16) | dos_attributes as u32
+}
+//由 Unix 模式推出对应的 DOS 属性
+pub fn dos_attributes_for_mode(mode: u32) -> u8 {
+    let mut attributes = 0;
+    if FileType::from_mode(mode) == Some(FileType::Directory) {
+        attributes |= DOS_DIRECTORY;
+    }
+    if mode & 0o222 == 0 && mode & PERMISSIONS_MASK != 0 {
+        attributes |= DOS_READ_ONLY;
+    }
+    attributes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::zip::Zip;
+    use fast_stream::stream::Stream;
+
+    #[test]
+    fn mode_attributes() {
+        assert_eq!(
+            FileType::from_mode(DEFAULT_SYMLINK_MODE),
+            Some(FileType::Symlink)
+        );
+        assert_eq!(FileType::from_mode(0o644), None);
+        assert_eq!(
+            dos_attributes_for_mode(S_IFDIR | 0o555),
+            DOS_DIRECTORY | DOS_READ_ONLY
+        );
+        assert_eq!(dos_attributes_for_mode(DEFAULT_FILE_MODE), 0);
+        assert_eq!(
+            external_attributes(DEFAULT_FILE_MODE, DOS_ARCHIVE),
+            0x81A4_0020
+        );
+    }
+    #[test]
+    fn entry_modes() {
+        let mut zip = Zip::create(Stream::empty());
+        zip.add_file(Stream::new(b"#!/bin/sh\n".to_vec().into()), "run.sh")
+            .unwrap();
+        zip.add_file(Stream::new(b"a".to_vec().into()), "a.txt")
+            .unwrap();
+        zip.add_folder("dir/").unwrap();
+        zip.directory("run.sh").unwrap().set_permissions(0o755);
+        zip.directory("a.txt").unwrap().set_permissions(0o444);
+        let mut output = Stream::empty();
+        zip.package(&mut output, &mut |_, _, _| {}).unwrap();
+
+        let mut zip = Zip::new(output).unwrap();
+        let directory = zip.directory("run.sh").unwrap();
+        assert_eq!(directory.unix_mode(), Some(S_IFREG | 0o755));
+        assert!(directory.is_executable());
+        let directory = zip.directory("a.txt").unwrap();
+        assert_eq!(directory.permissions(), Some(0o444));
+        assert_eq!(directory.dos_attributes() & DOS_READ_ONLY, DOS_READ_ONLY);
+        assert!(!directory.is_executable());
+        let directory = zip.directory("dir/").unwrap();
+        assert_eq!(directory.file_type(), FileType::Directory);
+        assert_eq!(directory.dos_attributes() & DOS_DIRECTORY, DOS_DIRECTORY);
+
+        //MS-DOS 创建的条目没有 Unix 模式，按 DOS 属性判断
+        directory.created_os = OS_MS_DOS;
+        assert_eq!(directory.unix_mode(), None);
+        assert!(directory.is_dir());
+    }
+}
@@ -1,6 +1,8 @@
+use crate::crypto::AesStrength;
 use crate::directory::{CompressionMethod, Directory};
-use crate::extra::{Extra};
-use crate::zip::{Cache, Parser};
+use crate::encoding::RawFileName;
+use crate::extra::ExtraFieldsWrapper;
+use crate::zip::{Cache, CompressionLevelWrapper, Parser};
 use crate::zip_file::ZipFile;
 use fast_stream::bytes::{Bytes, ValueRead, ValueWrite};
 use fast_stream::endian::Endian;
@@ -12,6 +14,7 @@ impl Directory<Cache> {
         Directory {
             r#type: Parser,
             data: self.data,
+            source: None,
             compressed: self.compressed,
             created_zip_spec: self.created_zip_spec,
             created_os: self.created_os,
@@ -19,6 +22,7 @@ impl Directory<Cache> {
             extract_os: self.extract_os,
             flags: self.flags,
             compression_method: self.compression_method,
+            compression_level: self.compression_level,
             last_modification_time: self.last_modification_time,
             last_modification_date: self.last_modification_date,
             crc_32_uncompressed_data: self.crc_32_uncompressed_data,
@@ -32,17 +36,25 @@ impl Directory<Cache> {
             external_file_attributes: self.external_file_attributes,
             offset_of_local_file_header: self.offset_of_local_file_header,
             file_name: self.file_name,
+            raw_file_name: self.raw_file_name,
             extra_fields: self.extra_fields,
             file_comment: self.file_comment,
             file: self.file.to_parser(),
+            password: self.password,
+            aes_strength: self.aes_strength,
+            zstd_level: self.zstd_level,
+            verify: self.verify,
+            adaptive: self.adaptive,
         }
     }
 }
 impl Directory<Parser> {
-    pub fn into_cache(self) -> Directory<Cache> {
-        Directory {
+    pub fn into_cache(mut self) -> Result<Directory<Cache>> {
+        self.load()?;
+        Ok(Directory {
             r#type: Cache,
             data: self.data,
+            source: None,
             compressed: self.compressed,
             created_zip_spec: self.created_zip_spec,
             created_os: self.created_os,
@@ -50,6 +62,7 @@ impl Directory<Parser> {
             extract_os: self.extract_os,
             flags: self.flags,
             compression_method: self.compression_method,
+            compression_level: self.compression_level,
             last_modification_time: self.last_modification_time,
             last_modification_date: self.last_modification_date,
             crc_32_uncompressed_data: self.crc_32_uncompressed_data,
@@ -63,10 +76,17 @@ impl Directory<Parser> {
             external_file_attributes: self.external_file_attributes,
             offset_of_local_file_header: self.offset_of_local_file_header,
             file_name: self.file_name,
+            raw_file_name: self.raw_file_name,
             extra_fields: self.extra_fields,
             file_comment: self.file_comment,
             file: self.file.into_cache(),
-        }
+            //密码不写入缓存，from_cache 后需要重新 with_password
+            password: None,
+            aes_strength: self.aes_strength,
+            zstd_level: self.zstd_level,
+            verify: self.verify,
+            adaptive: self.adaptive,
+        })
     }
 }
 
@@ -84,6 +104,7 @@ impl ValueWrite for Directory<Cache> {
         stream.write_value(self.extract_os)?;
         stream.write_value(self.flags)?;
         stream.write_value(self.compression_method)?;
+        stream.write_value(self.compression_level.map(CompressionLevelWrapper))?;
         stream.write_value(self.last_modification_time)?;
         stream.write_value(self.last_modification_date)?;
         stream.write_value(self.crc_32_uncompressed_data)?;
@@ -97,9 +118,14 @@ impl ValueWrite for Directory<Cache> {
         stream.write_value(self.external_file_attributes)?;
         stream.write_value(self.offset_of_local_file_header)?;
         stream.write_value(self.file_name)?;
-        stream.write_value(self.extra_fields)?;
+        stream.write_value(self.raw_file_name)?;
+        stream.write_value(ExtraFieldsWrapper(self.extra_fields))?;
         stream.write_value(self.file_comment)?;
         stream.write_value(self.file)?;
+        stream.write_value(self.adaptive)?;
+        stream.write_value(self.verify)?;
+        stream.write_value(self.aes_strength.map(|strength| strength as u8))?;
+        stream.write_value(self.zstd_level)?;
         Ok(stream)
     }
 }
@@ -113,25 +139,32 @@ impl ValueRead for Directory<Cache> {
         let extract_os: u8 = stream.read_value()?;
         let flags: u16 = stream.read_value()?;
         let compression_method: CompressionMethod = stream.read_value()?;
+        let compression_level: Option<CompressionLevelWrapper> = stream.read_value()?;
         let last_modification_time: u16 = stream.read_value()?;
         let last_modification_date: u16 = stream.read_value()?;
         let crc_32_uncompressed_data: u32 = stream.read_value()?;
-        let compressed_size: u32 = stream.read_value()?;
-        let uncompressed_size: u32 = stream.read_value()?;
+        let compressed_size: u64 = stream.read_value()?;
+        let uncompressed_size: u64 = stream.read_value()?;
         let file_name_length: u16 = stream.read_value()?;
         let extra_field_length: u16 = stream.read_value()?;
         let file_comment_length: u16 = stream.read_value()?;
         let number_of_starts: u16 = stream.read_value()?;
         let internal_file_attributes: u16 = stream.read_value()?;
         let external_file_attributes: u32 = stream.read_value()?;
-        let offset_of_local_file_header: u32 = stream.read_value()?;
+        let offset_of_local_file_header: u64 = stream.read_value()?;
         let file_name: String = stream.read_value()?;
-        let extra_fields: Vec<Extra> = stream.read_value()?;
+        let raw_file_name: Option<RawFileName> = stream.read_value()?;
+        let extra_fields = stream.read_value::<ExtraFieldsWrapper>()?.0;
         let file_comment: Vec<u8> = stream.read_value()?;
         let file: ZipFile<Cache> = stream.read_value()?;
+        let adaptive: bool = stream.read_value()?;
+        let verify: bool = stream.read_value()?;
+        let aes_strength: Option<u8> = stream.read_value()?;
+        let zstd_level: Option<i32> = stream.read_value()?;
         Ok(Self {
             r#type: Cache,
             data: data.into(),
+            source: None,
             compressed,
             created_zip_spec,
             created_os,
@@ -139,6 +172,7 @@ impl ValueRead for Directory<Cache> {
             extract_os,
             flags,
             compression_method,
+            compression_level: compression_level.map(|level| level.0),
             last_modification_time,
             last_modification_date,
             crc_32_uncompressed_data,
@@ -152,9 +186,15 @@ impl ValueRead for Directory<Cache> {
             external_file_attributes,
             offset_of_local_file_header,
             file_name,
+            raw_file_name,
             extra_fields,
             file_comment,
             file,
+            verify,
+            password: None,
+            aes_strength: aes_strength.and_then(AesStrength::from_u8),
+            zstd_level,
+            adaptive,
         })
     }
 }
@@ -13,7 +13,8 @@ impl EoCd<Parser> {
             entries: self.entries,
             size: self.size,
             offset: self.offset,
-            comment_length: self.comment_length,
+            comment: self.comment,
+            zip64: self.zip64,
         }
     }
 }
@@ -27,7 +28,8 @@ impl EoCd<Cache> {
             entries: self.entries,
             size: self.size,
             offset: self.offset,
-            comment_length: self.comment_length,
+            comment: self.comment,
+            zip64: self.zip64,
         }
     }
 }
@@ -41,7 +43,8 @@ impl ValueRead for EoCd<Cache> {
             entries: stream.read_value()?,
             size: stream.read_value()?,
             offset: stream.read_value()?,
-            comment_length: stream.read_value()?,
+            comment: stream.read_value()?,
+            zip64: stream.read_value()?,
         })
     }
 }
@@ -55,7 +58,8 @@ impl ValueWrite for EoCd<Cache> {
         stream.write_value(self.entries)?;
         stream.write_value(self.size)?;
         stream.write_value(self.offset)?;
-        stream.write_value(self.comment_length)?;
+        stream.write_value(self.comment)?;
+        stream.write_value(self.zip64)?;
         Ok(stream)
     }
 }
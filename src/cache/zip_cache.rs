@@ -1,5 +1,8 @@
+use crate::adaptive::Adaptive;
 use crate::directory::Directory;
+use crate::encoding::FileNameEncoding;
 use crate::eocd::EoCd;
+use crate::error::ZipError;
 use crate::zip::{Cache, CompressionLevelWrapper, Parser, Zip};
 use fast_stream::bytes::{Bytes, ValueRead, ValueWrite};
 use fast_stream::endian::Endian;
@@ -8,20 +11,28 @@ use indexmap::IndexMap;
 use std::time::Instant;
 
 impl Zip<Parser> {
-    pub fn into_cache(self) -> Zip<Cache> {
+    //Custom 文件名编码不能缓存，返回 CustomEncodingCache
+    pub fn into_cache(self) -> std::io::Result<Zip<Cache>> {
+        if let FileNameEncoding::Custom(_) = self.file_name_encoding {
+            return Err(ZipError::CustomEncodingCache.into());
+        }
         let mut directories = IndexMap::new();
         for (k, v) in self.directories {
-            directories.insert(k, v.into_cache());
+            directories.insert(k, v.into_cache()?);
         }
-        Zip {
+        Ok(Zip {
             stream_size: self.stream_size,
             stream: self.stream,
             crc32_computer: self.crc32_computer,
+            zip64: self.zip64,
+            file_name_encoding: self.file_name_encoding,
             eo_cd: self.eo_cd.map(|e| e.to_cache()),
             write_clear: self.write_clear,
             compression_level: self.compression_level,
+            adaptive: self.adaptive,
+            verify: self.verify,
             directories,
-        }
+        })
     }
 }
 impl Zip<Cache> {
@@ -42,9 +53,13 @@ impl Zip<Cache> {
             stream_size: self.stream_size,
             stream: self.stream,
             crc32_computer: self.crc32_computer,
+            zip64: self.zip64,
+            file_name_encoding: self.file_name_encoding,
             eo_cd: self.eo_cd.map(|e| e.to_parser()),
             write_clear: self.write_clear,
             compression_level: self.compression_level,
+            adaptive: self.adaptive,
+            verify: self.verify,
             directories,
         }
     }
@@ -59,6 +74,7 @@ impl ValueRead for Zip<Cache> {
             None
         };
         let crc32_computer: bool = stream.read_value()?;
+        let zip64: bool = stream.read_value()?;
         let eo_cd: Option<EoCd<Cache>> = if stream.read_value::<bool>()? {
             Some(stream.read_value()?)
         } else {
@@ -66,6 +82,9 @@ impl ValueRead for Zip<Cache> {
         };
         let write_clear: bool = stream.read_value()?;
         let compression_level: CompressionLevelWrapper = stream.read_value()?;
+        let adaptive: Option<Adaptive> = stream.read_value()?;
+        let verify: bool = stream.read_value()?;
+        let file_name_encoding: FileNameEncoding = stream.read_value()?;
         let directories_len: u64 = stream.read_value()?;
         let mut directories = IndexMap::with_capacity(directories_len as usize);
         for _ in 0..directories_len {
@@ -77,9 +96,13 @@ impl ValueRead for Zip<Cache> {
             stream_size,
             stream: data,
             crc32_computer,
+            zip64,
+            file_name_encoding,
             eo_cd,
             write_clear,
             compression_level: compression_level.0,
+            adaptive,
+            verify,
             directories,
         })
     }
@@ -95,12 +118,16 @@ impl ValueWrite for Zip<Cache> {
             stream.append(&mut data)?;
         }
         stream.write_value(self.crc32_computer)?;
+        stream.write_value(self.zip64)?;
         stream.write_value(self.eo_cd.is_some())?;
         if let Some(eo_cd) = self.eo_cd {
             stream.write_value(eo_cd)?;
         }
         stream.write_value(self.write_clear)?;
         stream.write_value(CompressionLevelWrapper(self.compression_level))?;
+        stream.write_value(self.adaptive)?;
+        stream.write_value(self.verify)?;
+        stream.write_value(self.file_name_encoding)?;
         stream.write_value(self.directories.len() as u64)?;
         let stream_time = Instant::now();
         for (k, v) in self.directories {
@@ -1,9 +1,41 @@
+use crate::extra::ExtraFieldsWrapper;
 use crate::zip::{Cache, Parser};
-use crate::zip_file::ZipFile;
+use crate::zip_file::{DataDescriptor, ZipFile};
 use fast_stream::bytes::{Bytes, ValueRead, ValueWrite};
 use fast_stream::endian::Endian;
 use fast_stream::stream::Stream;
 use std::io::Result;
+#[derive(Debug, Clone)]
+pub struct DataDescriptorWrapper(pub Option<DataDescriptor>);
+impl ValueRead for DataDescriptorWrapper {
+    fn read(stream: &mut Stream) -> Result<Self> {
+        if !stream.read_value::<bool>()? {
+            return Ok(DataDescriptorWrapper(None));
+        }
+        Ok(DataDescriptorWrapper(Some(DataDescriptor {
+            signature: stream.read_value()?,
+            crc32: stream.read_value()?,
+            compressed_size: stream.read_value()?,
+            uncompressed_size: stream.read_value()?,
+            zip64: stream.read_value()?,
+        })))
+    }
+}
+impl ValueWrite for DataDescriptorWrapper {
+    fn write(self, endian: &Endian) -> Result<Stream> {
+        let mut stream = Stream::empty();
+        stream.with_endian(endian.clone());
+        stream.write_value(self.0.is_some())?;
+        if let Some(data_descriptor) = self.0 {
+            stream.write_value(data_descriptor.signature)?;
+            stream.write_value(data_descriptor.crc32)?;
+            stream.write_value(data_descriptor.compressed_size)?;
+            stream.write_value(data_descriptor.uncompressed_size)?;
+            stream.write_value(data_descriptor.zip64)?;
+        }
+        Ok(stream)
+    }
+}
 impl ZipFile<Parser> {
     pub fn into_cache(self) -> ZipFile<Cache> {
         ZipFile {
@@ -20,6 +52,7 @@ impl ZipFile<Parser> {
             file_name_length: self.file_name_length,
             extra_field_length: self.extra_field_length,
             file_name: self.file_name,
+            raw_file_name: self.raw_file_name,
             extra_fields: self.extra_fields,
             data_descriptor: self.data_descriptor,
             data_position: self.data_position,
@@ -42,6 +75,7 @@ impl ZipFile<Cache> {
             file_name_length: self.file_name_length,
             extra_field_length: self.extra_field_length,
             file_name: self.file_name,
+            raw_file_name: self.raw_file_name,
             extra_fields: self.extra_fields,
             data_descriptor: self.data_descriptor,
             data_position: self.data_position,
@@ -64,8 +98,9 @@ impl ValueRead for ZipFile<Cache> {
             file_name_length: stream.read_value()?,
             extra_field_length: stream.read_value()?,
             file_name: stream.read_value()?,
-            extra_fields: stream.read_value()?,
-            data_descriptor: stream.read_value()?,
+            raw_file_name: stream.read_value()?,
+            extra_fields: stream.read_value::<ExtraFieldsWrapper>()?.0,
+            data_descriptor: stream.read_value::<DataDescriptorWrapper>()?.0,
             data_position: stream.read_value()?,
         };
         Ok(file)
@@ -87,8 +122,9 @@ impl ValueWrite for ZipFile<Cache> {
         stream.write_value(self.file_name_length)?;
         stream.write_value(self.extra_field_length)?;
         stream.write_value(self.file_name)?;
-        stream.write_value(self.extra_fields)?;
-        stream.write_value(self.data_descriptor)?;
+        stream.write_value(self.raw_file_name)?;
+        stream.write_value(ExtraFieldsWrapper(self.extra_fields))?;
+        stream.write_value(DataDescriptorWrapper(self.data_descriptor))?;
         stream.write_value(self.data_position)?;
         Ok(stream)
     }
@@ -0,0 +1,563 @@
+use crate::directory::CompressionMethod;
+use crate::error::ZipError;
+use fast_stream::deflate::CompressionLevel;
+use fast_stream::stream::Stream;
+use flate2::Compression;
+use flate2::write::DeflateEncoder;
+#[cfg(feature = "lzma")]
+use std::io::{Cursor, ErrorKind};
+use std::io::{Error, Read, Result, Write};
+
+const CHUNK_SIZE: usize = 64 * 1024;
+//general purpose bit 1、2 由压缩方式解释
+pub const METHOD_FLAGS_MASK: u16 = 0x0006;
+//general purpose bit 1，LZMA 数据带 EOS 结束标记
+pub const LZMA_EOS_FLAG: u16 = 0x0002;
+#[cfg(feature = "lzma")]
+const LZMA_VERSION: [u8; 2] = [9, 20];
+#[cfg(feature = "lzma")]
+const LZMA_PROPERTIES_SIZE: usize = 5;
+#[cfg(feature = "lzma")]
+const LZMA_ALONE_HEADER_SIZE: usize = LZMA_PROPERTIES_SIZE + 8;
+
+//压缩方式的编解码分发，Deflate 使用 flate2，package 和 package_stream 的输出一致，其它方式由对应的 feature 提供
+pub fn can_compress(method: &CompressionMethod) -> bool {
+    match method {
+        CompressionMethod::Deflate => true,
+        #[cfg(feature = "bzip2")]
+        CompressionMethod::BZIP2 => true,
+        #[cfg(feature = "lzma")]
+        CompressionMethod::LZMA | CompressionMethod::XZ => true,
+        #[cfg(feature = "zstd")]
+        CompressionMethod::Zstd => true,
+        _ => false,
+    }
+}
+pub fn can_decompress(method: &CompressionMethod) -> bool {
+    match method {
+        CompressionMethod::Store | CompressionMethod::Deflate => true,
+        #[cfg(feature = "bzip2")]
+        CompressionMethod::BZIP2 => true,
+        #[cfg(feature = "deflate64")]
+        CompressionMethod::Deflate64 => true,
+        #[cfg(feature = "lzma")]
+        CompressionMethod::LZMA | CompressionMethod::XZ => true,
+        #[cfg(feature = "zstd")]
+        CompressionMethod::Zstd => true,
+        _ => false,
+    }
+}
+//读取时除 Store 外的数据都需要解压，不支持的方式保持原样，打包时直接复制
+pub fn is_compressed(method: &CompressionMethod) -> bool {
+    *method != CompressionMethod::Store
+}
+//压缩后需要写入 general purpose bit 1、2 的值
+pub fn method_flags(method: &CompressionMethod) -> u16 {
+    match method {
+        CompressionMethod::LZMA => LZMA_EOS_FLAG,
+        _ => 0,
+    }
+}
+//新写入的数据只能使用 Store 或可压缩的方式
+pub fn check_compress(name: &str, method: &CompressionMethod) -> Result<()> {
+    if *method == CompressionMethod::Store || can_compress(method) {
+        Ok(())
+    } else {
+        Err(unsupported(name, method))
+    }
+}
+fn unsupported(name: &str, method: &CompressionMethod) -> Error {
+    ZipError::UnsupportedMethod {
+        name: name.to_string(),
+        method: method.value(),
+    }
+    .into()
+}
+//与 CompressionLevelWrapper 的数值一致，DefaultCompression 视为 6
+pub fn level_number(level: &CompressionLevel) -> u32 {
+    match level {
+        CompressionLevel::NoCompression => 0,
+        CompressionLevel::BestSpeed => 1,
+        CompressionLevel::BestCompression => 9,
+        CompressionLevel::UberCompression => 10,
+        CompressionLevel::DefaultLevel | CompressionLevel::DefaultCompression => 6,
+    }
+}
+//zstd 的级别范围是 1~22，默认 3，单独指定了 zstd 级别时优先使用
+pub fn zstd_level(level: &CompressionLevel, zstd_level: Option<i32>) -> i32 {
+    if let Some(zstd_level) = zstd_level {
+        return zstd_level;
+    }
+    match level {
+        CompressionLevel::NoCompression | CompressionLevel::BestSpeed => 1,
+        CompressionLevel::BestCompression => 19,
+        CompressionLevel::UberCompression => 22,
+        CompressionLevel::DefaultLevel | CompressionLevel::DefaultCompression => 3,
+    }
+}
+//zstd 还支持负数的快速级别，范围以库为准
+#[cfg(feature = "zstd")]
+pub fn check_zstd_level(name: &str, level: i32) -> Result<()> {
+    let range = zstd::compression_level_range();
+    if range.contains(&level) {
+        return Ok(());
+    }
+    Err(ZipError::InvalidLevel {
+        name: name.to_string(),
+        level,
+        min: *range.start(),
+        max: *range.end(),
+    }
+    .into())
+}
+#[cfg(not(feature = "zstd"))]
+pub fn check_zstd_level(name: &str, _level: i32) -> Result<()> {
+    Err(ZipError::UnsupportedFeature {
+        name: name.to_string(),
+        feature: "zstd",
+    }
+    .into())
+}
+//流式压缩器，写入原始数据，finish 时写出剩余的压缩数据
+pub trait Encoder: Write {
+    fn finish(self: Box<Self>) -> Result<()>;
+}
+struct StoreEncoder<'a>(&'a mut dyn Write);
+impl Write for StoreEncoder<'_> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.0.write(buf)
+    }
+    fn flush(&mut self) -> Result<()> {
+        self.0.flush()
+    }
+}
+impl Encoder for StoreEncoder<'_> {
+    fn finish(self: Box<Self>) -> Result<()> {
+        Ok(())
+    }
+}
+impl<W: Write> Encoder for DeflateEncoder<W> {
+    fn finish(self: Box<Self>) -> Result<()> {
+        (*self).finish().map(|_| ())
+    }
+}
+#[cfg(feature = "bzip2")]
+impl<W: Write> Encoder for bzip2::write::BzEncoder<W> {
+    fn finish(self: Box<Self>) -> Result<()> {
+        (*self).finish().map(|_| ())
+    }
+}
+#[cfg(feature = "lzma")]
+impl<W: Write> Encoder for liblzma::write::XzEncoder<W> {
+    fn finish(self: Box<Self>) -> Result<()> {
+        (*self).finish().map(|_| ())
+    }
+}
+#[cfg(feature = "zstd")]
+impl<W: Write> Encoder for zstd::stream::write::Encoder<'static, W> {
+    fn finish(self: Box<Self>) -> Result<()> {
+        (*self).finish().map(|_| ())
+    }
+}
+//按压缩方式创建写入 output 的压缩器，Store 原样写入
+#[cfg_attr(not(feature = "zstd"), allow(unused_variables))]
+pub fn encoder<'a>(
+    name: &str,
+    method: &CompressionMethod,
+    output: &'a mut dyn Write,
+    level: &CompressionLevel,
+    zstd_level: Option<i32>,
+) -> Result<Box<dyn Encoder + 'a>> {
+    match method {
+        CompressionMethod::Store => Ok(Box::new(StoreEncoder(output))),
+        CompressionMethod::Deflate => {
+            let level = Compression::new(level_number(level));
+            Ok(Box::new(DeflateEncoder::new(output, level)))
+        }
+        #[cfg(feature = "bzip2")]
+        CompressionMethod::BZIP2 => {
+            let level = bzip2::Compression::new(level_number(level).clamp(1, 9));
+            Ok(Box::new(bzip2::write::BzEncoder::new(output, level)))
+        }
+        #[cfg(feature = "lzma")]
+        CompressionMethod::LZMA => {
+            let options = liblzma::stream::LzmaOptions::new_preset(level_number(level).min(9))
+                .map_err(Error::other)?;
+            let lzma = liblzma::stream::Stream::new_lzma_encoder(&options).map_err(Error::other)?;
+            let output = LzmaZipWriter {
+                output,
+                header: vec![],
+            };
+            Ok(Box::new(liblzma::write::XzEncoder::new_stream(
+                output, lzma,
+            )))
+        }
+        #[cfg(feature = "lzma")]
+        CompressionMethod::XZ => {
+            let preset = level_number(level).min(9);
+            Ok(Box::new(liblzma::write::XzEncoder::new(output, preset)))
+        }
+        #[cfg(feature = "zstd")]
+        CompressionMethod::Zstd => {
+            let level = self::zstd_level(level, zstd_level);
+            let encoder = zstd::stream::write::Encoder::new(output, level)?;
+            Ok(Box::new(encoder))
+        }
+        _ => Err(unsupported(name, method)),
+    }
+}
+//原地压缩，返回压缩后的大小
+pub fn compress_callback(
+    name: &str,
+    method: &CompressionMethod,
+    stream: &mut Stream,
+    level: &CompressionLevel,
+    zstd_level: Option<i32>,
+    callback: &mut impl FnMut(usize),
+) -> Result<usize> {
+    match method {
+        method if can_compress(method) => encode(name, method, stream, level, zstd_level, callback),
+        _ => Err(unsupported(name, method)),
+    }
+}
+//自适应模式先压缩再比较大小，没有变小时保留原数据并返回 None
+pub fn compress_smaller(
+    name: &str,
+    method: &CompressionMethod,
+    stream: &mut Stream,
+    level: &CompressionLevel,
+    zstd_level: Option<i32>,
+    callback: &mut impl FnMut(usize),
+) -> Result<Option<usize>> {
+    if !can_compress(method) {
+        return Err(unsupported(name, method));
+    }
+    let compressed = encode_data(name, method, stream, level, zstd_level, callback)?;
+    if compressed.len() as u64 >= stream.length() {
+        stream.seek_start()?;
+        return Ok(None);
+    }
+    replace(stream, &compressed)?;
+    Ok(Some(compressed.len()))
+}
+//原地解压，LZMA 需要 flags 和解压后的大小来还原 .lzma 头
+pub fn decompress_callback(
+    name: &str,
+    method: &CompressionMethod,
+    stream: &mut Stream,
+    flags: u16,
+    uncompressed_size: u64,
+    callback: &mut impl FnMut(usize),
+) -> Result<()> {
+    match method {
+        method if can_decompress(method) => {
+            let input = read_all(stream)?;
+            let decoder = decoder(name, method, Box::new(&input[..]), flags, uncompressed_size)?;
+            decode(stream, decoder, callback)
+        }
+        _ => Err(unsupported(name, method)),
+    }
+}
+pub fn decompress(
+    name: &str,
+    method: &CompressionMethod,
+    stream: &mut Stream,
+    flags: u16,
+    uncompressed_size: u64,
+) -> Result<()> {
+    decompress_callback(name, method, stream, flags, uncompressed_size, &mut |_| {})
+}
+//流式解码，不需要把压缩数据整个读入内存
+#[cfg_attr(not(feature = "lzma"), allow(unused_variables))]
+pub fn decoder<'a>(
+    name: &str,
+    method: &CompressionMethod,
+    input: Box<dyn Read + 'a>,
+    flags: u16,
+    uncompressed_size: u64,
+) -> Result<Box<dyn Read + 'a>> {
+    match method {
+        CompressionMethod::Store => Ok(input),
+        CompressionMethod::Deflate => Ok(Box::new(flate2::read::DeflateDecoder::new(input))),
+        #[cfg(feature = "bzip2")]
+        CompressionMethod::BZIP2 => Ok(Box::new(bzip2::read::BzDecoder::new(input))),
+        //Deflate64 只支持解压
+        #[cfg(feature = "deflate64")]
+        CompressionMethod::Deflate64 => Ok(Box::new(deflate64::Deflate64Decoder::new(input))),
+        #[cfg(feature = "lzma")]
+        CompressionMethod::LZMA => {
+            let mut input = input;
+            let header = lzma_alone_header(&mut input, flags, uncompressed_size)?;
+            let lzma = liblzma::stream::Stream::new_lzma_decoder(u64::MAX).map_err(Error::other)?;
+            Ok(Box::new(liblzma::read::XzDecoder::new_stream(
+                Cursor::new(header).chain(input),
+                lzma,
+            )))
+        }
+        #[cfg(feature = "lzma")]
+        CompressionMethod::XZ => Ok(Box::new(liblzma::read::XzDecoder::new(input))),
+        #[cfg(feature = "zstd")]
+        CompressionMethod::Zstd => Ok(Box::new(zstd::stream::read::Decoder::new(input)?)),
+        _ => Err(unsupported(name, method)),
+    }
+}
+//ZIP 中的 LZMA: 版本(2) + 属性长度(2) + 属性(5) + 数据
+//.lzma(alone): 属性(5) + 解压大小(8，未知时为 -1) + 数据
+//编码器输出的 .lzma 头改写为 ZIP 中的 LZMA 头，之后的数据原样写出
+#[cfg(feature = "lzma")]
+struct LzmaZipWriter<'a> {
+    output: &'a mut dyn Write,
+    header: Vec<u8>,
+}
+#[cfg(feature = "lzma")]
+impl Write for LzmaZipWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        if self.header.len() < LZMA_ALONE_HEADER_SIZE {
+            let size = buf.len().min(LZMA_ALONE_HEADER_SIZE - self.header.len());
+            self.header.extend_from_slice(&buf[..size]);
+            if self.header.len() == LZMA_ALONE_HEADER_SIZE {
+                self.output.write_all(&LZMA_VERSION)?;
+                self.output
+                    .write_all(&(LZMA_PROPERTIES_SIZE as u16).to_le_bytes())?;
+                self.output
+                    .write_all(&self.header[..LZMA_PROPERTIES_SIZE])?;
+            }
+            return Ok(size);
+        }
+        self.output.write(buf)
+    }
+    fn flush(&mut self) -> Result<()> {
+        self.output.flush()
+    }
+}
+//读出 ZIP 中的 LZMA 头，转换为 .lzma 头，之后的数据原样交给解码器
+#[cfg(feature = "lzma")]
+fn lzma_alone_header(input: &mut dyn Read, flags: u16, uncompressed_size: u64) -> Result<Vec<u8>> {
+    let invalid = || Error::new(ErrorKind::InvalidData, "Invalid LZMA header");
+    let mut header = [0u8; 4 + LZMA_PROPERTIES_SIZE];
+    input.read_exact(&mut header).map_err(|e| match e.kind() {
+        ErrorKind::UnexpectedEof => invalid(),
+        _ => e,
+    })?;
+    let properties_size = u16::from_le_bytes([header[2], header[3]]) as usize;
+    if properties_size != LZMA_PROPERTIES_SIZE {
+        return Err(invalid());
+    }
+    //有 EOS 标记时大小写 -1，由结束标记决定数据的结尾
+    let size = if flags & LZMA_EOS_FLAG != 0 {
+        u64::MAX
+    } else {
+        uncompressed_size
+    };
+    let mut alone = Vec::with_capacity(LZMA_ALONE_HEADER_SIZE);
+    alone.extend_from_slice(&header[4..]);
+    alone.extend_from_slice(&size.to_le_bytes());
+    Ok(alone)
+}
+fn read_all(stream: &mut Stream) -> Result<Vec<u8>> {
+    let mut input = vec![];
+    stream.seek_start()?;
+    stream.read_to_end(&mut input)?;
+    Ok(input)
+}
+//分块读出解码结果并替换原数据
+fn decode<R: Read>(
+    stream: &mut Stream,
+    mut decoder: R,
+    callback: &mut impl FnMut(usize),
+) -> Result<()> {
+    let mut output = stream.copy_empty()?;
+    let mut buffer = vec![0u8; CHUNK_SIZE];
+    loop {
+        let size = decoder.read(&mut buffer)?;
+        if size == 0 {
+            break;
+        }
+        output.write_all(&buffer[..size])?;
+        callback(size);
+    }
+    output.seek_start()?;
+    *stream = output;
+    Ok(())
+}
+//分块写入编码器，回调的是已处理的原始字节数
+fn encode(
+    name: &str,
+    method: &CompressionMethod,
+    stream: &mut Stream,
+    level: &CompressionLevel,
+    zstd_level: Option<i32>,
+    callback: &mut impl FnMut(usize),
+) -> Result<usize> {
+    let compressed = encode_data(name, method, stream, level, zstd_level, callback)?;
+    replace(stream, &compressed)?;
+    Ok(compressed.len())
+}
+//压缩结果单独返回，原数据保持不变
+fn encode_data(
+    name: &str,
+    method: &CompressionMethod,
+    stream: &mut Stream,
+    level: &CompressionLevel,
+    zstd_level: Option<i32>,
+    callback: &mut impl FnMut(usize),
+) -> Result<Vec<u8>> {
+    stream.seek_start()?;
+    let mut compressed = vec![];
+    let mut encoder = encoder(name, method, &mut compressed, level, zstd_level)?;
+    let mut buffer = vec![0u8; CHUNK_SIZE];
+    loop {
+        let size = stream.read(&mut buffer)?;
+        if size == 0 {
+            break;
+        }
+        encoder.write_all(&buffer[..size])?;
+        callback(size);
+    }
+    encoder.finish()?;
+    Ok(compressed)
+}
+fn replace(stream: &mut Stream, data: &[u8]) -> Result<()> {
+    let mut output = stream.copy_empty()?;
+    output.write_all(data)?;
+    output.seek_start()?;
+    *stream = output;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //Python lzma 的 LZMA1 原始流加上 ZIP 中的 LZMA 头，带 EOS 结束标记
+    #[cfg(feature = "lzma")]
+    const LZMA_DATA: [u8; 48] = [
+        0x09, 0x14, 0x05, 0x00, 0x5d, 0x00, 0x00, 0x01, 0x00, 0x00, 0x3d, 0x1a, 0x4a, 0x62, 0x29,
+        0xad, 0x51, 0x9f, 0x50, 0xb5, 0xf1, 0xb3, 0xb3, 0x9b, 0x5e, 0x9d, 0xb4, 0x58, 0x7f, 0xc9,
+        0xd1, 0xb9, 0x4f, 0xa2, 0x50, 0x7d, 0x53, 0x4f, 0xff, 0x6f, 0x70, 0x6b, 0x6d, 0xdf, 0xfd,
+        0x36, 0x3c, 0x00,
+    ];
+
+    fn encode_all(method: &CompressionMethod, data: &[u8]) -> Vec<u8> {
+        let mut output = vec![];
+        let level = CompressionLevel::DefaultCompression;
+        let mut encoder = encoder("a.txt", method, &mut output, &level, None).unwrap();
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap();
+        output
+    }
+    fn decode_all(method: &CompressionMethod, data: &[u8], uncompressed_size: u64) -> Vec<u8> {
+        let flags = method_flags(method);
+        let mut decoder =
+            decoder("a.txt", method, Box::new(data), flags, uncompressed_size).unwrap();
+        let mut output = vec![];
+        decoder.read_to_end(&mut output).unwrap();
+        output
+    }
+
+    #[cfg(feature = "lzma")]
+    #[test]
+    fn lzma_known_vector() {
+        let plain = b"zip lzma header conversion\n".repeat(4);
+        let data = decode_all(&CompressionMethod::LZMA, &LZMA_DATA, plain.len() as u64);
+        assert_eq!(data, plain);
+    }
+    #[cfg(feature = "lzma")]
+    #[test]
+    fn lzma_round_trip() {
+        let plain = b"lzma round trip\n".repeat(500);
+        let data = encode_all(&CompressionMethod::LZMA, &plain);
+        //.lzma 头被改写为 版本 + 属性长度 + 属性
+        assert_eq!(data[..4], [LZMA_VERSION[0], LZMA_VERSION[1], 5, 0]);
+        let data = decode_all(&CompressionMethod::LZMA, &data, plain.len() as u64);
+        assert_eq!(data, plain);
+    }
+    #[cfg(feature = "lzma")]
+    #[test]
+    fn lzma_invalid_header() {
+        let mut data = LZMA_DATA;
+        data[2] = 4;
+        let result = decoder("a.txt", &CompressionMethod::LZMA, Box::new(&data[..]), 0, 0);
+        assert_eq!(result.err().unwrap().kind(), ErrorKind::InvalidData);
+        let result = decoder(
+            "a.txt",
+            &CompressionMethod::LZMA,
+            Box::new(&data[..3]),
+            0,
+            0,
+        );
+        assert_eq!(result.err().unwrap().kind(), ErrorKind::InvalidData);
+    }
+    #[cfg(feature = "lzma")]
+    #[test]
+    fn lzma_alone_header_size() {
+        //有 EOS 标记时大小写 -1，否则写中央目录中的解压大小
+        for (flags, size) in [(LZMA_EOS_FLAG, u64::MAX), (0, 108)] {
+            let mut input = &LZMA_DATA[..];
+            let header = lzma_alone_header(&mut input, flags, 108).unwrap();
+            assert_eq!(header.len(), LZMA_ALONE_HEADER_SIZE);
+            assert_eq!(header[..5], [0x5d, 0x00, 0x00, 0x01, 0x00]);
+            assert_eq!(header[5..], size.to_le_bytes());
+            assert_eq!(input, &LZMA_DATA[9..]);
+        }
+    }
+    #[cfg(feature = "bzip2")]
+    #[test]
+    fn bzip2_round_trip() {
+        let plain = b"bzip2 round trip\n".repeat(500);
+        let data = encode_all(&CompressionMethod::BZIP2, &plain);
+        assert_eq!(data[..3], *b"BZh");
+        let data = decode_all(&CompressionMethod::BZIP2, &data, plain.len() as u64);
+        assert_eq!(data, plain);
+    }
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn zstd_levels() {
+        let plain = b"zstd levels\n".repeat(500);
+        let method = CompressionMethod::Zstd;
+        let level = CompressionLevel::DefaultCompression;
+        assert_eq!(zstd_level(&level, None), 3);
+        assert_eq!(zstd_level(&CompressionLevel::UberCompression, Some(-5)), -5);
+        for zstd_level in [-5, 1, 22] {
+            check_zstd_level("a.txt", zstd_level).unwrap();
+            let mut output = vec![];
+            let mut encoder =
+                encoder("a.txt", &method, &mut output, &level, Some(zstd_level)).unwrap();
+            encoder.write_all(&plain).unwrap();
+            encoder.finish().unwrap();
+            assert_eq!(decode_all(&method, &output, plain.len() as u64), plain);
+        }
+        let error = ZipError::from(check_zstd_level("a.txt", 23).unwrap_err());
+        assert!(matches!(error, ZipError::InvalidLevel { max: 22, .. }));
+    }
+    #[test]
+    fn deflate_round_trip() {
+        let plain = b"deflate round trip\n".repeat(500);
+        let data = encode_all(&CompressionMethod::Deflate, &plain);
+        assert!(data.len() < plain.len());
+        let data = decode_all(&CompressionMethod::Deflate, &data, plain.len() as u64);
+        assert_eq!(data, plain);
+    }
+    //package 原地压缩和 package_stream 流式压缩的结果一致
+    #[test]
+    fn deflate_same_output() {
+        let plain = b"deflate same output\n".repeat(500);
+        let method = CompressionMethod::Deflate;
+        let level = CompressionLevel::UberCompression;
+        let mut stream = Stream::new(plain.clone().into());
+        compress_callback("a.txt", &method, &mut stream, &level, None, &mut |_| {}).unwrap();
+        let mut output = vec![];
+        let mut encoder = encoder("a.txt", &method, &mut output, &level, None).unwrap();
+        encoder.write_all(&plain).unwrap();
+        encoder.finish().unwrap();
+        assert_eq!(stream.take_data().unwrap(), output);
+        let data = decode_all(&method, &output, plain.len() as u64);
+        assert_eq!(data, plain);
+    }
+    #[test]
+    fn unsupported_method() {
+        let result = decoder("a.txt", &CompressionMethod::PPMd, Box::new(&[][..]), 0, 0);
+        let error = ZipError::from(result.err().unwrap());
+        assert!(matches!(error, ZipError::UnsupportedMethod { .. }));
+    }
+}
@@ -0,0 +1,56 @@
+//CRC-32 (IEEE 802.3)，用于 Unicode 扩展字段、加密头校验和解压校验
+const CRC32_TABLE: [u32; 256] = make_table();
+
+const fn make_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut value = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            value = if value & 1 != 0 {
+                0xEDB88320 ^ (value >> 1)
+            } else {
+                value >> 1
+            };
+            bit += 1;
+        }
+        table[i] = value;
+        i += 1;
+    }
+    table
+}
+
+//不做取反的单字节更新，ZipCrypto 的密钥更新直接使用
+pub fn update_byte(crc: u32, byte: u8) -> u32 {
+    CRC32_TABLE[((crc ^ byte as u32) & 0xFF) as usize] ^ (crc >> 8)
+}
+
+#[derive(Debug, Clone)]
+pub struct Crc32 {
+    value: u32,
+}
+impl Default for Crc32 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl Crc32 {
+    pub fn new() -> Self {
+        Self { value: 0xFFFFFFFF }
+    }
+    pub fn update(&mut self, data: &[u8]) {
+        for byte in data {
+            self.value = update_byte(self.value, *byte);
+        }
+    }
+    pub fn value(&self) -> u32 {
+        !self.value
+    }
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = Crc32::new();
+    crc.update(data);
+    crc.value()
+}
@@ -0,0 +1,565 @@
+use crate::crc32::update_byte;
+use crate::error::ZipError;
+#[cfg(feature = "aes")]
+use aes::cipher::{BlockEncrypt, KeyInit, generic_array::GenericArray};
+#[cfg(feature = "aes")]
+use hmac::{Hmac, Mac};
+#[cfg(feature = "aes")]
+use sha1::Sha1;
+#[cfg(feature = "aes")]
+use std::io::{Error, Take};
+use std::io::{ErrorKind, Read, Result, Write};
+
+//general purpose bit 0，条目数据已加密
+pub const ENCRYPTED_FLAG: u16 = 0x0001;
+
+//传统 PKWARE 加密(ZipCrypto)的加密头长度
+pub const ZIP_CRYPTO_HEADER_SIZE: usize = 12;
+
+//https://www.winzip.com/en/support/aes-encryption/
+//AE-1 保留 crc32，AE-2 的 crc32 为 0，完整性只由认证码保证
+pub const AE_1: u16 = 1;
+pub const AE_2: u16 = 2;
+pub const AES_VENDOR_ID: u16 = 0x4541; //"AE"
+pub const AES_EXTRA_DATA_SIZE: u16 = 7;
+const PASSWORD_VERIFIER_SIZE: usize = 2;
+const AUTHENTICATION_CODE_SIZE: usize = 10;
+#[cfg(feature = "aes")]
+const KEY_ITERATIONS: u32 = 1000;
+#[cfg(feature = "aes")]
+const BLOCK_SIZE: usize = 16;
+#[cfg(feature = "aes")]
+const BUFFER_SIZE: usize = 64 * 1024;
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AesStrength {
+    Aes128 = 1,
+    Aes192 = 2,
+    Aes256 = 3,
+}
+impl AesStrength {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            1 => Some(AesStrength::Aes128),
+            2 => Some(AesStrength::Aes192),
+            3 => Some(AesStrength::Aes256),
+            _ => None,
+        }
+    }
+    pub fn key_size(&self) -> usize {
+        8 * (*self as usize + 1)
+    }
+    pub fn salt_size(&self) -> usize {
+        4 * (*self as usize + 1)
+    }
+    //加密后比原数据多出的字节数: salt + 密码校验值 + 认证码
+    pub fn overhead(&self) -> usize {
+        self.salt_size() + PASSWORD_VERIFIER_SIZE + AUTHENTICATION_CODE_SIZE
+    }
+}
+
+//PBKDF2-HMAC-SHA1 派生出 加密密钥 + HMAC 密钥 + 2 字节密码校验值
+#[cfg(feature = "aes")]
+fn derive_keys(password: &[u8], salt: &[u8], strength: AesStrength) -> Vec<u8> {
+    let mut keys = vec![0u8; strength.key_size() * 2 + PASSWORD_VERIFIER_SIZE];
+    pbkdf2::pbkdf2_hmac::<Sha1>(password, salt, KEY_ITERATIONS, &mut keys);
+    keys
+}
+//WinZip 使用从 1 开始的小端计数器的 CTR 模式，加密和解密相同，可以分块处理
+#[cfg(feature = "aes")]
+enum AesCipher {
+    Aes128(aes::Aes128),
+    Aes192(aes::Aes192),
+    Aes256(aes::Aes256),
+}
+#[cfg(feature = "aes")]
+struct AesCtr {
+    cipher: AesCipher,
+    counter: u128,
+    block: [u8; BLOCK_SIZE],
+    used: usize,
+}
+#[cfg(feature = "aes")]
+impl AesCtr {
+    fn new(key: &[u8]) -> Self {
+        let cipher = match key.len() {
+            16 => AesCipher::Aes128(aes::Aes128::new(GenericArray::from_slice(key))),
+            24 => AesCipher::Aes192(aes::Aes192::new(GenericArray::from_slice(key))),
+            _ => AesCipher::Aes256(aes::Aes256::new(GenericArray::from_slice(key))),
+        };
+        AesCtr {
+            cipher,
+            counter: 0,
+            block: [0; BLOCK_SIZE],
+            used: BLOCK_SIZE,
+        }
+    }
+    fn apply(&mut self, data: &mut [u8]) {
+        for byte in data {
+            if self.used == BLOCK_SIZE {
+                self.counter += 1;
+                let mut block = GenericArray::clone_from_slice(&self.counter.to_le_bytes());
+                match &self.cipher {
+                    AesCipher::Aes128(cipher) => cipher.encrypt_block(&mut block),
+                    AesCipher::Aes192(cipher) => cipher.encrypt_block(&mut block),
+                    AesCipher::Aes256(cipher) => cipher.encrypt_block(&mut block),
+                }
+                self.block.copy_from_slice(&block);
+                self.used = 0;
+            }
+            *byte ^= self.block[self.used];
+            self.used += 1;
+        }
+    }
+}
+#[cfg(feature = "aes")]
+fn new_hmac(key: &[u8]) -> Hmac<Sha1> {
+    <Hmac<Sha1> as Mac>::new_from_slice(key).expect("HMAC accepts keys of any size")
+}
+//流式加密，先写 salt 和密码校验值，finish 时写认证码
+#[cfg(feature = "aes")]
+pub struct AesWriter<'a> {
+    output: &'a mut dyn Write,
+    ctr: AesCtr,
+    mac: Hmac<Sha1>,
+    buffer: Vec<u8>,
+}
+#[cfg(feature = "aes")]
+impl<'a> AesWriter<'a> {
+    pub fn new(
+        _name: &str,
+        output: &'a mut dyn Write,
+        password: &[u8],
+        strength: AesStrength,
+    ) -> Result<Self> {
+        let mut salt = vec![0u8; strength.salt_size()];
+        getrandom::getrandom(&mut salt).map_err(Error::other)?;
+        let keys = derive_keys(password, &salt, strength);
+        let key_size = strength.key_size();
+        output.write_all(&salt)?;
+        output.write_all(&keys[key_size * 2..])?;
+        Ok(AesWriter {
+            output,
+            ctr: AesCtr::new(&keys[..key_size]),
+            mac: new_hmac(&keys[key_size..key_size * 2]),
+            buffer: vec![],
+        })
+    }
+    pub fn finish(self) -> Result<()> {
+        let code = self.mac.finalize().into_bytes();
+        self.output.write_all(&code[..AUTHENTICATION_CODE_SIZE])
+    }
+}
+#[cfg(feature = "aes")]
+impl Write for AesWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.buffer.clear();
+        self.buffer.extend_from_slice(buf);
+        self.ctr.apply(&mut self.buffer);
+        self.mac.update(&self.buffer);
+        self.output.write_all(&self.buffer)?;
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> Result<()> {
+        self.output.flush()
+    }
+}
+#[cfg(not(feature = "aes"))]
+pub struct AesWriter<'a> {
+    output: &'a mut dyn Write,
+}
+#[cfg(not(feature = "aes"))]
+impl<'a> AesWriter<'a> {
+    pub fn new(
+        name: &str,
+        _output: &'a mut dyn Write,
+        _password: &[u8],
+        _strength: AesStrength,
+    ) -> Result<Self> {
+        Err(ZipError::UnsupportedMethod {
+            name: name.to_string(),
+            method: crate::directory::CompressionMethod::AES.value(),
+        }
+        .into())
+    }
+    pub fn finish(self) -> Result<()> {
+        Ok(())
+    }
+}
+#[cfg(not(feature = "aes"))]
+impl Write for AesWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.output.write(buf)
+    }
+    fn flush(&mut self) -> Result<()> {
+        self.output.flush()
+    }
+}
+//输出 salt + 密码校验值 + 密文 + 认证码
+pub fn aes_encrypt(
+    name: &str,
+    password: &[u8],
+    strength: AesStrength,
+    data: &[u8],
+) -> Result<Vec<u8>> {
+    let mut output = Vec::with_capacity(data.len() + strength.overhead());
+    let mut writer = AesWriter::new(name, &mut output, password, strength)?;
+    writer.write_all(data)?;
+    writer.finish()?;
+    Ok(output)
+}
+//流式解密，new 时校验密码，finish 时校验认证码
+//size 为加密数据的总长度: salt + 密码校验值 + 密文 + 认证码
+#[cfg(feature = "aes")]
+pub struct AesReader<R> {
+    name: String,
+    position: u64,
+    input: Take<R>,
+    ctr: AesCtr,
+    mac: Hmac<Sha1>,
+}
+#[cfg(feature = "aes")]
+impl<R: Read> AesReader<R> {
+    pub fn new(
+        name: &str,
+        position: u64,
+        mut input: R,
+        password: &[u8],
+        strength: AesStrength,
+        size: u64,
+    ) -> std::result::Result<Self, ZipError> {
+        if size < strength.overhead() as u64 {
+            return Err(truncated(name, position));
+        }
+        let salt_size = strength.salt_size();
+        let mut header = vec![0u8; salt_size + PASSWORD_VERIFIER_SIZE];
+        read_exact(name, position, &mut input, &mut header)?;
+        let key_size = strength.key_size();
+        let keys = derive_keys(password, &header[..salt_size], strength);
+        if keys[key_size * 2..] != header[salt_size..] {
+            return Err(ZipError::InvalidPassword {
+                name: name.to_string(),
+            });
+        }
+        Ok(AesReader {
+            name: name.to_string(),
+            position,
+            input: input.take(size - strength.overhead() as u64),
+            ctr: AesCtr::new(&keys[..key_size]),
+            mac: new_hmac(&keys[key_size..key_size * 2]),
+        })
+    }
+    //解码器不一定读到密文的结尾，剩余的部分只参与认证码的计算
+    pub fn finish(mut self) -> std::result::Result<(), ZipError> {
+        let mut buffer = vec![0u8; BUFFER_SIZE];
+        loop {
+            let size = self.input.read(&mut buffer)?;
+            if size == 0 {
+                break;
+            }
+            self.mac.update(&buffer[..size]);
+        }
+        let mut code = [0u8; AUTHENTICATION_CODE_SIZE];
+        let mut input = self.input.into_inner();
+        read_exact(&self.name, self.position, &mut input, &mut code)?;
+        if self.mac.verify_truncated_left(&code).is_err() {
+            return Err(ZipError::AuthenticationFailed { name: self.name });
+        }
+        Ok(())
+    }
+}
+#[cfg(feature = "aes")]
+impl<R: Read> Read for AesReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let size = self.input.read(buf)?;
+        self.mac.update(&buf[..size]);
+        self.ctr.apply(&mut buf[..size]);
+        Ok(size)
+    }
+}
+#[cfg(not(feature = "aes"))]
+pub struct AesReader<R> {
+    input: R,
+}
+#[cfg(not(feature = "aes"))]
+impl<R: Read> AesReader<R> {
+    pub fn new(
+        name: &str,
+        _position: u64,
+        _input: R,
+        _password: &[u8],
+        _strength: AesStrength,
+        _size: u64,
+    ) -> std::result::Result<Self, ZipError> {
+        Err(ZipError::UnsupportedMethod {
+            name: name.to_string(),
+            method: crate::directory::CompressionMethod::AES.value(),
+        })
+    }
+    pub fn finish(self) -> std::result::Result<(), ZipError> {
+        Ok(())
+    }
+}
+#[cfg(not(feature = "aes"))]
+impl<R: Read> Read for AesReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        self.input.read(buf)
+    }
+}
+//position 为数据在归档中的位置
+pub fn aes_decrypt(
+    name: &str,
+    position: u64,
+    password: &[u8],
+    strength: AesStrength,
+    data: &[u8],
+) -> std::result::Result<Vec<u8>, ZipError> {
+    let size = data.len() as u64;
+    let mut reader = AesReader::new(name, position, data, password, strength, size)?;
+    let mut output = Vec::with_capacity(data.len().saturating_sub(strength.overhead()));
+    reader.read_to_end(&mut output)?;
+    reader.finish()?;
+    Ok(output)
+}
+//加密数据短于固定的头尾
+fn truncated(name: &str, position: u64) -> ZipError {
+    ZipError::Truncated {
+        name: Some(name.to_string()),
+        offset: position,
+    }
+}
+fn read_exact(
+    name: &str,
+    position: u64,
+    input: &mut impl Read,
+    buf: &mut [u8],
+) -> std::result::Result<(), ZipError> {
+    input.read_exact(buf).map_err(|e| match e.kind() {
+        ErrorKind::UnexpectedEof => truncated(name, position),
+        _ => e.into(),
+    })
+}
+
+//APPNOTE 6.1 传统 PKWARE 加密，只支持解密
+#[derive(Debug, Clone)]
+pub struct ZipCrypto {
+    keys: [u32; 3],
+}
+impl ZipCrypto {
+    pub fn new(password: &[u8]) -> Self {
+        let mut zip_crypto = ZipCrypto {
+            keys: [0x12345678, 0x23456789, 0x34567890],
+        };
+        for byte in password {
+            zip_crypto.update_keys(*byte);
+        }
+        zip_crypto
+    }
+    fn update_keys(&mut self, byte: u8) {
+        self.keys[0] = update_byte(self.keys[0], byte);
+        self.keys[1] = self.keys[1]
+            .wrapping_add(self.keys[0] & 0xFF)
+            .wrapping_mul(134775813)
+            .wrapping_add(1);
+        self.keys[2] = update_byte(self.keys[2], (self.keys[1] >> 24) as u8);
+    }
+    fn stream_byte(&self) -> u8 {
+        let temp = (self.keys[2] | 2) as u16;
+        (temp.wrapping_mul(temp ^ 1) >> 8) as u8
+    }
+    pub fn decrypt(&mut self, data: &mut [u8]) {
+        for byte in data {
+            *byte ^= self.stream_byte();
+            self.update_keys(*byte);
+        }
+    }
+}
+//流式解密，new 时读出加密头并用 check_byte 校验密码
+pub struct ZipCryptoReader<R> {
+    input: R,
+    zip_crypto: ZipCrypto,
+}
+impl<R: Read> ZipCryptoReader<R> {
+    //check_byte 为 crc32 的最高字节，有 DataDescriptor(bit 3)时为修改时间的高字节
+    pub fn new(
+        name: &str,
+        position: u64,
+        mut input: R,
+        password: &[u8],
+        check_byte: u8,
+    ) -> std::result::Result<Self, ZipError> {
+        let mut header = [0u8; ZIP_CRYPTO_HEADER_SIZE];
+        read_exact(name, position, &mut input, &mut header)?;
+        let mut zip_crypto = ZipCrypto::new(password);
+        zip_crypto.decrypt(&mut header);
+        if header[ZIP_CRYPTO_HEADER_SIZE - 1] != check_byte {
+            return Err(ZipError::InvalidPassword {
+                name: name.to_string(),
+            });
+        }
+        Ok(ZipCryptoReader { input, zip_crypto })
+    }
+}
+impl<R: Read> Read for ZipCryptoReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let size = self.input.read(buf)?;
+        self.zip_crypto.decrypt(&mut buf[..size]);
+        Ok(size)
+    }
+}
+pub fn zip_crypto_decrypt(
+    name: &str,
+    position: u64,
+    password: &[u8],
+    check_byte: u8,
+    data: &[u8],
+) -> std::result::Result<Vec<u8>, ZipError> {
+    let mut reader = ZipCryptoReader::new(name, position, data, password, check_byte)?;
+    let mut output = Vec::with_capacity(data.len().saturating_sub(ZIP_CRYPTO_HEADER_SIZE));
+    reader.read_to_end(&mut output)?;
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //Info-ZIP 3.0 `zip -0 -P test` 生成，带 DataDescriptor，校验字节为修改时间的高字节
+    const ZIP_CRYPTO_DATA: [u8; 28] = [
+        0xb6, 0xc1, 0xd2, 0xc6, 0xc7, 0xb1, 0x5f, 0x7a, 0x25, 0x5d, 0x3d, 0xfa, 0x83, 0x06, 0xd5,
+        0x06, 0x37, 0xf0, 0x68, 0x16, 0xea, 0x92, 0x79, 0x4a, 0xd3, 0xc9, 0x1b, 0xe2,
+    ];
+    const ZIP_CRYPTO_CHECK_BYTE: u8 = 0x4d;
+    const ZIP_CRYPTO_PLAIN: &[u8] = b"hello zipcrypto\n";
+
+    //按 WinZip AES 规范独立生成，密码 test，salt 为 0, 1, 2...
+    #[cfg(feature = "aes")]
+    const AES_128_DATA: [u8; 58] = [
+        0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x6b, 0x2b, 0xd9, 0xb8, 0xe9, 0xbb, 0xe7,
+        0x04, 0x38, 0x5f, 0xde, 0x5c, 0xf1, 0xe7, 0xbe, 0x34, 0x7e, 0x16, 0x02, 0x30, 0x30, 0x6b,
+        0x87, 0xd1, 0x87, 0xed, 0x21, 0x8a, 0xfd, 0x12, 0xc3, 0x24, 0x98, 0x6a, 0xe8, 0x1d, 0x77,
+        0xc0, 0x26, 0x28, 0xd8, 0x0c, 0x59, 0x52, 0x23, 0x5c, 0x3b, 0x28, 0x19, 0x55,
+    ];
+    #[cfg(feature = "aes")]
+    const AES_256_DATA: [u8; 66] = [
+        0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e,
+        0x0f, 0xdb, 0x6c, 0x98, 0x52, 0xc5, 0x5e, 0xe9, 0x1a, 0xcf, 0x06, 0xaf, 0x49, 0x48, 0xff,
+        0xe1, 0x54, 0x1b, 0x4d, 0x74, 0x18, 0x00, 0x9e, 0x1a, 0x7c, 0x4b, 0xf3, 0x9b, 0x8d, 0x61,
+        0xa0, 0x54, 0xee, 0x98, 0x22, 0x97, 0x59, 0xaf, 0x17, 0x9a, 0x32, 0x63, 0xba, 0x57, 0xd9,
+        0xd1, 0x8c, 0x25, 0xb9, 0x77, 0x9e,
+    ];
+    #[cfg(feature = "aes")]
+    const AES_PLAIN: &[u8] = b"hello winzip aes, more than one block\n";
+
+    #[test]
+    fn zip_crypto_known_vector() {
+        let data = zip_crypto_decrypt("a.txt", 0, b"test", ZIP_CRYPTO_CHECK_BYTE, &ZIP_CRYPTO_DATA)
+            .unwrap();
+        assert_eq!(data, ZIP_CRYPTO_PLAIN);
+    }
+    #[test]
+    fn zip_crypto_reader_chunks() {
+        let mut reader = ZipCryptoReader::new(
+            "a.txt",
+            0,
+            &ZIP_CRYPTO_DATA[..],
+            b"test",
+            ZIP_CRYPTO_CHECK_BYTE,
+        )
+        .unwrap();
+        let mut data = vec![];
+        let mut buffer = [0u8; 3];
+        loop {
+            let size = reader.read(&mut buffer).unwrap();
+            if size == 0 {
+                break;
+            }
+            data.extend_from_slice(&buffer[..size]);
+        }
+        assert_eq!(data, ZIP_CRYPTO_PLAIN);
+    }
+    #[test]
+    fn zip_crypto_wrong_password() {
+        let result = zip_crypto_decrypt(
+            "a.txt",
+            0,
+            b"wrong",
+            ZIP_CRYPTO_CHECK_BYTE,
+            &ZIP_CRYPTO_DATA,
+        );
+        assert!(matches!(result, Err(ZipError::InvalidPassword { .. })));
+    }
+    #[test]
+    fn zip_crypto_truncated() {
+        let result = zip_crypto_decrypt(
+            "a.txt",
+            100,
+            b"test",
+            ZIP_CRYPTO_CHECK_BYTE,
+            &ZIP_CRYPTO_DATA[..ZIP_CRYPTO_HEADER_SIZE - 1],
+        );
+        assert!(matches!(
+            result,
+            Err(ZipError::Truncated { offset: 100, .. })
+        ));
+    }
+    #[cfg(feature = "aes")]
+    #[test]
+    fn aes_known_vector() {
+        let data = aes_decrypt("a.txt", 0, b"test", AesStrength::Aes128, &AES_128_DATA).unwrap();
+        assert_eq!(data, AES_PLAIN);
+        let data = aes_decrypt("a.txt", 0, b"test", AesStrength::Aes256, &AES_256_DATA).unwrap();
+        assert_eq!(data, AES_PLAIN);
+    }
+    #[cfg(feature = "aes")]
+    #[test]
+    fn aes_wrong_password() {
+        let result = aes_decrypt("a.txt", 0, b"wrong", AesStrength::Aes256, &AES_256_DATA);
+        assert!(matches!(result, Err(ZipError::InvalidPassword { .. })));
+    }
+    #[cfg(feature = "aes")]
+    #[test]
+    fn aes_authentication_failed() {
+        let mut data = AES_256_DATA;
+        data[20] ^= 1;
+        let result = aes_decrypt("a.txt", 0, b"test", AesStrength::Aes256, &data);
+        assert!(matches!(result, Err(ZipError::AuthenticationFailed { .. })));
+        //只读了一部分也要在 finish 时发现
+        let mut reader =
+            AesReader::new("a.txt", 0, &data[..], b"test", AesStrength::Aes256, 66).unwrap();
+        reader.read_exact(&mut [0u8; 4]).unwrap();
+        assert!(matches!(
+            reader.finish(),
+            Err(ZipError::AuthenticationFailed { .. })
+        ));
+    }
+    #[cfg(feature = "aes")]
+    #[test]
+    fn aes_round_trip() {
+        let plain = b"round trip across several aes blocks\n".repeat(20);
+        for strength in [
+            AesStrength::Aes128,
+            AesStrength::Aes192,
+            AesStrength::Aes256,
+        ] {
+            let data = aes_encrypt("a.txt", b"test", strength, &plain).unwrap();
+            assert_eq!(data.len(), plain.len() + strength.overhead());
+            assert_eq!(
+                aes_decrypt("a.txt", 0, b"test", strength, &data).unwrap(),
+                plain
+            );
+            //分块写入与一次写入的结果可以同样解密
+            let mut data = vec![];
+            let mut writer = AesWriter::new("a.txt", &mut data, b"test", strength).unwrap();
+            for chunk in plain.chunks(7) {
+                writer.write_all(chunk).unwrap();
+            }
+            writer.finish().unwrap();
+            assert_eq!(
+                aes_decrypt("a.txt", 0, b"test", strength, &data).unwrap(),
+                plain
+            );
+        }
+    }
+}
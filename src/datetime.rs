@@ -0,0 +1,140 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+//1601-01-01 到 1970-01-01 的秒数，NTFS 时间以 100 纳秒为单位
+const NTFS_EPOCH_OFFSET: i64 = 11_644_473_600;
+const NTFS_TICKS_PER_SECOND: i64 = 10_000_000;
+//DOS 时间可表示的范围 1980-01-01 ~ 2107-12-31
+const DOS_MIN_YEAR: i64 = 1980;
+const DOS_MAX_YEAR: i64 = 2107;
+
+//返回 (秒, 纳秒)，纳秒总是非负
+pub fn unix_time(time: SystemTime) -> (i64, u32) {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(duration) => (duration.as_secs() as i64, duration.subsec_nanos()),
+        Err(e) => {
+            let duration = e.duration();
+            let seconds = -(duration.as_secs() as i64);
+            match duration.subsec_nanos() {
+                0 => (seconds, 0),
+                nanos => (seconds - 1, 1_000_000_000 - nanos),
+            }
+        }
+    }
+}
+pub fn system_time(seconds: i64, nanos: u32) -> SystemTime {
+    if seconds >= 0 {
+        UNIX_EPOCH + Duration::new(seconds as u64, nanos)
+    } else {
+        UNIX_EPOCH - Duration::from_secs(seconds.unsigned_abs()) + Duration::from_nanos(nanos as u64)
+    }
+}
+
+//https://howardhinnant.github.io/date_algorithms.html
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = (if z >= 0 { z } else { z - 146_096 }) / 146_097;
+    let doe = (z - era * 146_097) as u64;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = (if mp < 10 { mp + 3 } else { mp - 9 }) as u32;
+    let year = yoe as i64 + era * 400;
+    (if month <= 2 { year + 1 } else { year }, month, day)
+}
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = (if year >= 0 { year } else { year - 399 }) / 400;
+    let yoe = (year - era * 400) as u64;
+    let mp = (if month > 2 { month - 3 } else { month + 9 }) as u64;
+    let doy = (153 * mp + 2) / 5 + day as u64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe as i64 - 719_468
+}
+
+//返回 (last_modification_time, last_modification_date)，使用 UTC，超出范围时取边界值
+pub fn to_dos(seconds: i64) -> (u16, u16) {
+    let days = seconds.div_euclid(86_400);
+    let seconds_of_day = seconds.rem_euclid(86_400);
+    let (year, month, day) = civil_from_days(days);
+    if year < DOS_MIN_YEAR {
+        return (0, (1 << 5) | 1);
+    }
+    if year > DOS_MAX_YEAR {
+        return (
+            (23 << 11) | (59 << 5) | 29,
+            (((DOS_MAX_YEAR - DOS_MIN_YEAR) as u16) << 9) | (12 << 5) | 31,
+        );
+    }
+    let hour = (seconds_of_day / 3600) as u16;
+    let minute = (seconds_of_day % 3600 / 60) as u16;
+    let second = (seconds_of_day % 60) as u16;
+    let time = (hour << 11) | (minute << 5) | (second / 2);
+    let date = (((year - DOS_MIN_YEAR) as u16) << 9) | ((month as u16) << 5) | day as u16;
+    (time, date)
+}
+pub fn from_dos(time: u16, date: u16) -> i64 {
+    let year = (date >> 9) as i64 + DOS_MIN_YEAR;
+    let month = ((date >> 5) & 0x0F).clamp(1, 12) as u32;
+    let day = (date & 0x1F).max(1) as u32;
+    let hour = (time >> 11) as i64;
+    let minute = ((time >> 5) & 0x3F) as i64;
+    let second = ((time & 0x1F) * 2) as i64;
+    days_from_civil(year, month, day) * 86_400 + hour * 3600 + minute * 60 + second
+}
+
+pub fn to_ntfs(seconds: i64, nanos: u32) -> u64 {
+    ((seconds + NTFS_EPOCH_OFFSET) * NTFS_TICKS_PER_SECOND + (nanos / 100) as i64).max(0) as u64
+}
+pub fn from_ntfs(ticks: u64) -> (i64, u32) {
+    let ticks = ticks as i64;
+    let seconds = ticks.div_euclid(NTFS_TICKS_PER_SECOND) - NTFS_EPOCH_OFFSET;
+    let nanos = (ticks.rem_euclid(NTFS_TICKS_PER_SECOND) * 100) as u32;
+    (seconds, nanos)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::zip::{FileOptions, Zip};
+    use fast_stream::stream::Stream;
+
+    //2023-11-14 22:13:21 UTC
+    const SECONDS: i64 = 1_700_000_001;
+
+    #[test]
+    fn dos_time() {
+        let (time, date) = to_dos(SECONDS);
+        assert_eq!(time, (22 << 11) | (13 << 5) | 10);
+        assert_eq!(date, (43 << 9) | (11 << 5) | 14);
+        //DOS 时间精度为 2 秒
+        assert_eq!(from_dos(time, date), SECONDS - 1);
+        assert_eq!(to_dos(0), (0, (1 << 5) | 1));
+        let (seconds, nanos) = from_ntfs(to_ntfs(SECONDS, 123_456_700));
+        assert_eq!((seconds, nanos), (SECONDS, 123_456_700));
+        assert_eq!(unix_time(system_time(-1, 500)), (-1, 500));
+    }
+    #[test]
+    fn entry_modified() {
+        let mut zip = Zip::create(Stream::empty());
+        let options = FileOptions::default().with_modified_unix(SECONDS);
+        zip.add_file_with_options(Stream::new(b"a".to_vec().into()), "a.txt", options.clone())
+            .unwrap();
+        zip.add_folder_with_options("dir/", options).unwrap();
+        let mut output = Stream::empty();
+        zip.package(&mut output, &mut |_, _, _| {}).unwrap();
+
+        let mut zip = Zip::new(output).unwrap();
+        for name in ["a.txt", "dir/"] {
+            let directory = zip.directory(name).unwrap();
+            assert_eq!(directory.modified(), system_time(SECONDS, 0));
+            let dos = (
+                directory.last_modification_time,
+                directory.last_modification_date,
+            );
+            assert_eq!(dos, to_dos(SECONDS));
+            directory.set_modified(system_time(SECONDS + 10, 0));
+            assert_eq!(directory.modified(), system_time(SECONDS + 10, 0));
+        }
+    }
+}
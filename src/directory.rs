@@ -1,47 +1,168 @@
+use crate::adaptive::Adaptive;
+use crate::attributes::{
+    dos_attributes_for_mode, external_attributes, FileType, DEFAULT_DIR_MODE, DEFAULT_FILE_MODE,
+    DEFAULT_SYMLINK_MODE, DOS_DIRECTORY, DOS_READ_ONLY, EXECUTABLE_MASK, OS_UNIX, PERMISSIONS_MASK,
+};
+use crate::codec;
+use crate::crc32::Crc32;
+use crate::crypto::{
+    self, AE_1, AE_2, AesReader, AesStrength, AesWriter, ENCRYPTED_FLAG, ZipCryptoReader,
+};
+use crate::datetime;
+use crate::encoding::{FileNameEncoding, RawFileName};
+use crate::entry_info::EntryInfo;
+use crate::eocd::ZIP64_ZIP_SPEC;
+use crate::error::ZipError;
 use crate::extra::Extra;
 use crate::magic::Magic;
 use crate::zip::Parser;
-use crate::zip_file::ZipFile;
+use crate::zip_file::{DataDescriptor, ZipFile};
 use fast_stream::bytes::{Bytes, ValueRead, ValueWrite};
-use fast_stream::deflate::{CompressionLevel, Deflate};
-use fast_stream::derive::NumToEnum;
+use fast_stream::deflate::CompressionLevel;
 use fast_stream::endian::Endian;
-use fast_stream::enum_to_bytes;
-use fast_stream::pin::Pin;
 use fast_stream::stream::Stream;
-use std::cmp::max;
+use std::cmp::{max, min};
 use std::fmt::Debug;
-use std::io::{Error, ErrorKind, Result, Seek, SeekFrom, Write};
+use std::io::{Error, ErrorKind, Read, Result, Seek, SeekFrom, Write};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::SystemTime;
+
+const COPY_CHUNK_SIZE: u64 = 1024 * 1024;
+//流式压缩时压缩后可能比原始数据略大，预估大小时额外留出的字节数
+const STREAM_SIZE_MARGIN: u64 = 4096;
+
+//统计写入的字节数，流式写入后得到压缩大小
+struct CountingWriter<'a, W: Write> {
+    output: &'a mut W,
+    count: u64,
+}
+impl<W: Write> Write for CountingWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let size = self.output.write(buf)?;
+        self.count += size as u64;
+        Ok(size)
+    }
+    fn flush(&mut self) -> Result<()> {
+        self.output.flush()
+    }
+}
+
+//解压校验时只计算 crc32 和大小
+struct ChecksumWriter {
+    crc32: Crc32,
+    size: u64,
+}
+impl Write for ChecksumWriter {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.crc32.update(buf);
+        self.size += buf.len() as u64;
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
 
 pub trait Size {
     fn size(&self) -> usize;
 }
-#[repr(u16)]
-#[derive(Debug, Clone, Default, PartialEq, NumToEnum)]
+//未列出的方式(如 Reduce、Tokenize、TERSE)保存为 Unknown，原样读写
+#[derive(Debug, Clone, Default, PartialEq)]
 pub enum CompressionMethod {
     #[default]
-    Store = 0x0000,
-    Shrink = 0x0001,
-    Implode = 0x0006,
-    Deflate = 0x0008,
-    Deflate64 = 0x0009,
-    BZIP2 = 0x000C,
-    LZMA = 0x000E,
-    XZ = 0x005F,
-    JPEG = 0x0060,
-    WavPack = 0x0061,
-    PPMd = 0x0062,
-    AES = 0x0063,
+    Store,
+    Shrink,
+    Implode,
+    Deflate,
+    Deflate64,
+    BZIP2,
+    LZMA,
+    Zstd,
+    XZ,
+    JPEG,
+    WavPack,
+    PPMd,
+    AES,
+    Unknown(u16),
 }
 impl CompressionMethod {
     pub const fn byte_size() -> usize {
         2
     }
+    pub fn value(&self) -> u16 {
+        match self {
+            CompressionMethod::Store => 0x0000,
+            CompressionMethod::Shrink => 0x0001,
+            CompressionMethod::Implode => 0x0006,
+            CompressionMethod::Deflate => 0x0008,
+            CompressionMethod::Deflate64 => 0x0009,
+            CompressionMethod::BZIP2 => 0x000C,
+            CompressionMethod::LZMA => 0x000E,
+            CompressionMethod::Zstd => 0x005D,
+            CompressionMethod::XZ => 0x005F,
+            CompressionMethod::JPEG => 0x0060,
+            CompressionMethod::WavPack => 0x0061,
+            CompressionMethod::PPMd => 0x0062,
+            CompressionMethod::AES => 0x0063,
+            CompressionMethod::Unknown(value) => *value,
+        }
+    }
+    //解压该方式所需的最低版本(version needed to extract)
+    pub fn version_needed(&self) -> u8 {
+        match self {
+            CompressionMethod::Store => 10,
+            CompressionMethod::Shrink | CompressionMethod::Implode => 10,
+            CompressionMethod::Deflate => 20,
+            CompressionMethod::Deflate64 => 21,
+            CompressionMethod::BZIP2 => 46,
+            CompressionMethod::AES => 51,
+            CompressionMethod::LZMA | CompressionMethod::XZ | CompressionMethod::Zstd => 63,
+            CompressionMethod::JPEG | CompressionMethod::WavPack | CompressionMethod::PPMd => 63,
+            CompressionMethod::Unknown(_) => 10,
+        }
+    }
+}
+impl From<u16> for CompressionMethod {
+    fn from(value: u16) -> Self {
+        match value {
+            0x0000 => CompressionMethod::Store,
+            0x0001 => CompressionMethod::Shrink,
+            0x0006 => CompressionMethod::Implode,
+            0x0008 => CompressionMethod::Deflate,
+            0x0009 => CompressionMethod::Deflate64,
+            0x000C => CompressionMethod::BZIP2,
+            0x000E => CompressionMethod::LZMA,
+            0x005D => CompressionMethod::Zstd,
+            0x005F => CompressionMethod::XZ,
+            0x0060 => CompressionMethod::JPEG,
+            0x0061 => CompressionMethod::WavPack,
+            0x0062 => CompressionMethod::PPMd,
+            0x0063 => CompressionMethod::AES,
+            value => CompressionMethod::Unknown(value),
+        }
+    }
+}
+impl From<CompressionMethod> for u16 {
+    fn from(value: CompressionMethod) -> Self {
+        value.value()
+    }
+}
+impl ValueRead for CompressionMethod {
+    fn read(stream: &mut Stream) -> Result<Self> {
+        Ok(stream.read_value::<u16>()?.into())
+    }
+}
+impl ValueWrite for CompressionMethod {
+    fn write(self, endian: &Endian) -> Result<Stream> {
+        let mut stream = Stream::empty();
+        stream.with_endian(endian.clone());
+        stream.write_value(self.value())?;
+        Ok(stream)
+    }
 }
-enum_to_bytes!(CompressionMethod, u16);
 impl Directory<Parser> {
     pub fn exec_un_compress_size(&mut self) -> usize {
-        if !self.compressed && self.compression_method == CompressionMethod::Deflate {
+        if !self.compressed && codec::can_compress(&self.compression_method) {
             self.data.length() as usize
         } else {
             0
@@ -51,9 +172,19 @@ impl Directory<Parser> {
         &mut self,
         crc32_computer: bool,
         compression_level: &CompressionLevel,
+        adaptive: Option<&Adaptive>,
         callback: &mut impl FnMut(usize),
     ) -> Result<()> {
-        if !self.compressed && self.compression_method == CompressionMethod::Deflate {
+        let adaptive = adaptive.filter(|_| self.adaptive);
+        if !self.compressed
+            && codec::can_compress(&self.compression_method)
+            && let Some(adaptive) = adaptive
+            && self.predict_store(adaptive)?
+        {
+            self.use_store();
+            callback(self.data.length() as usize);
+        }
+        if !self.compressed && codec::can_compress(&self.compression_method) {
             let crc_32_uncompressed_data = if crc32_computer {
                 self.data.init_crc32();
                 self.data.hash_computer()?;
@@ -64,23 +195,493 @@ impl Directory<Parser> {
             self.crc_32_uncompressed_data = crc_32_uncompressed_data; //crc32 设置为0也能安装，网页可以忽略计算加快速度
             self.file.crc_32_uncompressed_data = crc_32_uncompressed_data;
             self.data.seek_start()?;
-            let compress_size = self.data.compress_callback(compression_level, callback)?;
-            self.compressed_size = compress_size as u32;
-            self.compressed = true;
+            let compression_level = self.compression_level.as_ref().unwrap_or(compression_level);
+            //自适应模式压缩后没有变小时保留原数据，换回 Store
+            let compress_size = match adaptive {
+                Some(_) => codec::compress_smaller(
+                    &self.file_name,
+                    &self.compression_method,
+                    &mut self.data,
+                    compression_level,
+                    self.zstd_level,
+                    callback,
+                )?,
+                None => Some(codec::compress_callback(
+                    &self.file_name,
+                    &self.compression_method,
+                    &mut self.data,
+                    compression_level,
+                    self.zstd_level,
+                    callback,
+                )?),
+            };
+            match compress_size {
+                Some(compress_size) => {
+                    self.update_method_flags();
+                    self.compressed_size = compress_size as u64;
+                    self.compressed = true;
+                    self.file.compressed_size = self.compressed_size;
+                }
+                None => self.use_store(),
+            }
+        }
+        //Store 的新数据原样写入，只需要补上大小和 crc32
+        if self.compression_method == CompressionMethod::Store && self.is_loaded() {
+            if crc32_computer && self.crc_32_uncompressed_data == 0 {
+                self.data.init_crc32();
+                self.data.hash_computer()?;
+                self.crc_32_uncompressed_data = self.data.crc32_value();
+                self.file.crc_32_uncompressed_data = self.crc_32_uncompressed_data;
+            }
+            self.compressed_size = self.data.length();
+            self.uncompressed_size = self.compressed_size;
             self.file.compressed_size = self.compressed_size;
+            self.file.uncompressed_size = self.uncompressed_size;
+        }
+        if let Some(strength) = self.aes_strength {
+            self.encrypt(strength)?;
+        }
+        self.data.seek_start()?;
+        Ok(())
+    }
+    //加密压缩后的数据，已经是 AES 的条目不重复加密
+    fn encrypt(&mut self, strength: AesStrength) -> Result<()> {
+        if self.compression_method == CompressionMethod::AES {
             return Ok(());
         }
+        let password = self.aes_password()?;
+        self.load()?;
+        let mut data = vec![];
+        self.data.seek_start()?;
+        self.data.read_to_end(&mut data)?;
+        let data = crypto::aes_encrypt(&self.file_name, &password, strength, &data)?;
+        self.replace_data(&data)?;
+        //没有计算 crc32 时使用 AE-2
+        let version = if self.crc_32_uncompressed_data == 0 {
+            AE_2
+        } else {
+            AE_1
+        };
+        self.use_aes(strength, version);
+        self.compressed = true;
+        self.compressed_size = data.len() as u64;
+        self.file.compressed_size = self.compressed_size;
+        Ok(())
+    }
+    fn aes_password(&self) -> Result<Vec<u8>> {
+        match &self.password {
+            Some(password) => Ok(password.clone()),
+            None => Err(ZipError::PasswordRequired {
+                name: self.file_name.clone(),
+            }
+            .into()),
+        }
+    }
+    //压缩方式改为 AES，实际的压缩方式记录在 AES 扩展字段中
+    fn use_aes(&mut self, strength: AesStrength, version: u16) {
+        let aes = Extra::Aes {
+            version,
+            strength,
+            compression_method: self.compression_method.clone(),
+        };
+        self.extra_fields
+            .retain(|extra_field| !matches!(extra_field, Extra::Aes { .. }));
+        self.file
+            .extra_fields
+            .retain(|extra_field| !matches!(extra_field, Extra::Aes { .. }));
+        self.extra_fields.push(aes.clone());
+        self.file.extra_fields.push(aes);
+        self.compression_method = CompressionMethod::AES;
+        self.file.compression_method = CompressionMethod::AES;
+        self.flags |= ENCRYPTED_FLAG;
+        self.file.flags |= ENCRYPTED_FLAG;
+    }
+    fn decrypt(&mut self) -> std::result::Result<(), ZipError> {
+        if self.compression_method == CompressionMethod::AES {
+            self.decrypt_aes()
+        } else if self.is_encrypted() {
+            self.decrypt_zip_crypto()
+        } else {
+            Ok(())
+        }
+    }
+    //ZipCrypto 解密后的条目重新打包时不再加密
+    fn decrypt_zip_crypto(&mut self) -> std::result::Result<(), ZipError> {
+        let mut data = vec![];
         self.data.seek_start()?;
+        self.data.read_to_end(&mut data)?;
+        let (password, check_byte) = self.zip_crypto_params()?;
+        let data = crypto::zip_crypto_decrypt(
+            &self.file_name,
+            self.file.data_position,
+            password,
+            check_byte,
+            &data,
+        )?;
+        self.replace_data(&data)?;
+        self.flags &= !ENCRYPTED_FLAG;
+        self.file.flags &= !ENCRYPTED_FLAG;
+        self.compressed_size = data.len() as u64;
+        self.file.compressed_size = self.compressed_size;
+        Ok(())
+    }
+    //返回密码和用于校验密码的字节
+    fn zip_crypto_params(&self) -> std::result::Result<(&[u8], u8), ZipError> {
+        //bit 6 为 PKWARE 强加密
+        if self.flags & 0x0040 != 0 {
+            return Err(ZipError::UnsupportedFeature {
+                name: self.file_name.clone(),
+                feature: "strong encryption",
+            });
+        }
+        let Some(password) = &self.password else {
+            return Err(ZipError::Encrypted {
+                name: self.file_name.clone(),
+            });
+        };
+        let check_byte = if self.file.flags & 0x0008 != 0 {
+            (self.last_modification_time >> 8) as u8
+        } else {
+            (self.crc_32_uncompressed_data >> 24) as u8
+        };
+        Ok((password, check_byte))
+    }
+    //解密后恢复为实际的压缩方式，并记录强度以便重新打包时再次加密
+    fn decrypt_aes(&mut self) -> std::result::Result<(), ZipError> {
+        let mut data = vec![];
+        self.data.seek_start()?;
+        self.data.read_to_end(&mut data)?;
+        let (password, strength, compression_method) = self.aes_params()?;
+        let data = crypto::aes_decrypt(
+            &self.file_name,
+            self.file.data_position,
+            password,
+            strength,
+            &data,
+        )?;
+        self.replace_data(&data)?;
+        self.extra_fields
+            .retain(|extra_field| !matches!(extra_field, Extra::Aes { .. }));
+        self.file
+            .extra_fields
+            .retain(|extra_field| !matches!(extra_field, Extra::Aes { .. }));
+        self.compressed = codec::is_compressed(&compression_method);
+        self.compression_method = compression_method.clone();
+        self.file.compression_method = compression_method;
+        self.flags &= !ENCRYPTED_FLAG;
+        self.file.flags &= !ENCRYPTED_FLAG;
+        self.compressed_size = data.len() as u64;
+        self.file.compressed_size = self.compressed_size;
+        self.aes_strength = Some(strength);
+        Ok(())
+    }
+    //返回密码、强度和实际的压缩方式
+    fn aes_params(&self) -> std::result::Result<(&[u8], AesStrength, CompressionMethod), ZipError> {
+        let Some((_, strength, compression_method)) = Extra::find_aes(&self.extra_fields)
+            .or_else(|| Extra::find_aes(&self.file.extra_fields))
+        else {
+            return Err(ZipError::UnsupportedMethod {
+                name: self.file_name.clone(),
+                method: CompressionMethod::AES.value(),
+            });
+        };
+        let Some(password) = &self.password else {
+            return Err(ZipError::Encrypted {
+                name: self.file_name.clone(),
+            });
+        };
+        Ok((password, strength, compression_method))
+    }
+    fn replace_data(&mut self, bytes: &[u8]) -> Result<()> {
+        let mut data = self.data.copy_empty()?;
+        data.write_all(bytes)?;
+        data.seek_start()?;
+        self.data = data;
+        Ok(())
+    }
+    //按扩展名和抽样熵预判是否值得压缩
+    fn predict_store(&mut self, adaptive: &Adaptive) -> Result<bool> {
+        if adaptive.is_store_extension(&self.file_name) {
+            return Ok(true);
+        }
+        self.data.seek_start()?;
+        let sample_size = min(self.data.length(), Adaptive::sample_size() as u64);
+        let sample = self.data.read_exact_size(sample_size)?;
+        self.data.seek_start()?;
+        Ok(Adaptive::is_incompressible(&sample))
+    }
+    fn use_store(&mut self) {
+        self.compression_method = CompressionMethod::Store;
+        self.file.compression_method = CompressionMethod::Store;
+        self.update_method_flags();
+    }
+    //bit 1、2 的含义由压缩方式决定，重新压缩后要与新方式一致
+    fn update_method_flags(&mut self) {
+        let method_flags = codec::method_flags(&self.compression_method);
+        self.flags = (self.flags & !codec::METHOD_FLAGS_MASK) | method_flags;
+        self.file.flags = (self.file.flags & !codec::METHOD_FLAGS_MASK) | method_flags;
+    }
+    //流式写入本地头(bit 3)、数据和 DataDescriptor，返回写入的字节数和对应的中央目录记录
+    //新增的未压缩数据边压缩边写入，条目本身保持不变；其它条目按 exec 的结果写入
+    //写入前由调用方通过 set_offset 设置本地头位置
+    pub fn write_stream<W: Write>(
+        &mut self,
+        output: &mut W,
+        crc32_computer: bool,
+        compression_level: &CompressionLevel,
+        adaptive: Option<&Adaptive>,
+        force_zip64: bool,
+        callback: &mut impl FnMut(usize),
+    ) -> Result<(u64, Directory<Parser>)> {
+        let streaming = self.is_loaded()
+            && !self.compressed
+            && (self.compression_method == CompressionMethod::Store
+                || codec::can_compress(&self.compression_method));
+        if streaming {
+            return self.write_stream_data(
+                output,
+                crc32_computer,
+                compression_level,
+                adaptive,
+                force_zip64,
+                callback,
+            );
+        }
+        let endian = Endian::Little;
+        self.exec(crc32_computer, compression_level, adaptive, callback)?;
+        self.update_file_name();
+        self.update_zip64(force_zip64);
+        self.update_version_needed();
+        //ZipCrypto 按 bit 3 选择校验字节，原样复制的加密条目保持原来的设置
+        if !self.is_encrypted() {
+            self.file.data_descriptor = Some(Self::empty_data_descriptor());
+        }
+        self.update_data_descriptor();
+
+        let mut file = self.file.clone();
+        let data_descriptor = file.data_descriptor.take();
+        let header = file.write(&endian)?.take_data()?;
+        output.write_all(&header)?;
+        let data_size = if self.source.is_some() {
+            self.copy_source(output)?
+        } else {
+            self.data.seek_start()?;
+            std::io::copy(&mut self.data, output)?
+        };
+        let mut written = header.len() as u64 + data_size;
+        if let Some(data_descriptor) = data_descriptor {
+            let data_descriptor = data_descriptor.write(&endian)?.take_data()?;
+            output.write_all(&data_descriptor)?;
+            written += data_descriptor.len() as u64;
+        }
+        Ok((written, self.clone_not_stream()))
+    }
+    fn write_stream_data<W: Write>(
+        &mut self,
+        output: &mut W,
+        crc32_computer: bool,
+        compression_level: &CompressionLevel,
+        adaptive: Option<&Adaptive>,
+        force_zip64: bool,
+        callback: &mut impl FnMut(usize),
+    ) -> Result<(u64, Directory<Parser>)> {
+        let endian = Endian::Little;
+        let mut entry = self.clone_not_stream();
+        let compressing = codec::can_compress(&entry.compression_method);
+        //数据写出后不能再换回 Store，只按扩展名和抽样预判
+        if let Some(adaptive) = adaptive.filter(|_| self.adaptive)
+            && compressing
+            && self.predict_store(adaptive)?
+        {
+            entry.use_store();
+            callback(self.data.length() as usize);
+        }
+        let method = entry.compression_method.clone();
+        let compressing = compressing && method != CompressionMethod::Store;
+        entry.update_method_flags();
+        let aes = match entry.aes_strength {
+            Some(strength) => {
+                entry.use_aes(strength, if crc32_computer { AE_1 } else { AE_2 });
+                Some((entry.aes_password()?, strength))
+            }
+            None => None,
+        };
+        //本地头在数据之前写出，按可能的最大压缩大小决定是否带 ZIP64，其中的大小写 0
+        let length = self.data.length();
+        let local_zip64 =
+            force_zip64 || length + length / 64 + STREAM_SIZE_MARGIN >= u32::MAX as u64;
+        entry.set_sizes(0, 0, 0);
+        entry.update_file_name();
+        entry.update_zip64(local_zip64);
+        entry.update_version_needed();
+        entry.file.data_descriptor = Some(Self::empty_data_descriptor());
+        entry.update_data_descriptor();
+        let mut file = entry.file.clone();
+        file.data_descriptor = None;
+        let header = file.write(&endian)?.take_data()?;
+        output.write_all(&header)?;
+
+        let level = entry
+            .compression_level
+            .as_ref()
+            .unwrap_or(compression_level);
+        let mut counter = CountingWriter { output, count: 0 };
+        let crc32 = match aes {
+            Some((password, strength)) => {
+                let mut writer =
+                    AesWriter::new(&self.file_name, &mut counter, &password, strength)?;
+                let crc32 = self.encode_data(&mut writer, &method, level, compressing, callback)?;
+                writer.finish()?;
+                crc32
+            }
+            None => self.encode_data(&mut counter, &method, level, compressing, callback)?,
+        };
+        let compressed_size = counter.count;
+        let crc32 = if crc32_computer { crc32 } else { 0 };
+        entry.set_sizes(crc32, compressed_size, length);
+        if !local_zip64 && compressed_size >= u32::MAX as u64 {
+            return Err(ZipError::LimitExceeded {
+                name: entry.file_name.clone(),
+                field: "compressed size",
+                value: compressed_size,
+                limit: u32::MAX as u64,
+            }
+            .into());
+        }
+        entry.update_zip64(local_zip64);
+        entry.update_data_descriptor();
+        entry.compressed = true;
+        let mut written = header.len() as u64 + compressed_size;
+        if let Some(data_descriptor) = entry.file.data_descriptor.clone() {
+            let data_descriptor = data_descriptor.write(&endian)?.take_data()?;
+            output.write_all(&data_descriptor)?;
+            written += data_descriptor.len() as u64;
+        }
+        Ok((written, entry))
+    }
+    //分块压缩写入 output，返回原始数据的 crc32
+    fn encode_data(
+        &mut self,
+        output: &mut dyn Write,
+        method: &CompressionMethod,
+        level: &CompressionLevel,
+        compressing: bool,
+        callback: &mut impl FnMut(usize),
+    ) -> Result<u32> {
+        let mut encoder = codec::encoder(&self.file_name, method, output, level, self.zstd_level)?;
+        let mut crc32 = Crc32::new();
+        let mut buffer = vec![0u8; COPY_CHUNK_SIZE as usize];
+        self.data.seek_start()?;
+        loop {
+            let size = self.data.read(&mut buffer)?;
+            if size == 0 {
+                break;
+            }
+            crc32.update(&buffer[..size]);
+            encoder.write_all(&buffer[..size])?;
+            if compressing {
+                callback(size);
+            }
+        }
+        encoder.finish()?;
+        self.data.seek_start()?;
+        Ok(crc32.value())
+    }
+    fn empty_data_descriptor() -> DataDescriptor {
+        DataDescriptor {
+            signature: true,
+            crc32: 0,
+            compressed_size: 0,
+            uncompressed_size: 0,
+            zip64: false,
+        }
+    }
+    fn set_sizes(&mut self, crc32: u32, compressed_size: u64, uncompressed_size: u64) {
+        self.crc_32_uncompressed_data = crc32;
+        self.compressed_size = compressed_size;
+        self.uncompressed_size = uncompressed_size;
+        self.file.crc_32_uncompressed_data = crc32;
+        self.file.compressed_size = compressed_size;
+        self.file.uncompressed_size = uncompressed_size;
+    }
+    fn lock_source(source: &Arc<Mutex<Stream>>) -> Result<MutexGuard<'_, Stream>> {
+        source.lock().map_err(|_| ZipError::SourcePoisoned.into())
+    }
+    pub fn is_loaded(&self) -> bool {
+        self.source.is_none()
+    }
+    //数据位置由本地头中文件名和扩展字段的长度决定，首次读取数据时才解析
+    fn data_position(&mut self) -> Result<u64> {
+        if self.file.data_position == 0
+            && let Some(source) = &self.source
+        {
+            let mut stream = Self::lock_source(source)?;
+            stream.seek(SeekFrom::Start(self.offset_of_local_file_header))?;
+            let file: ZipFile<Parser> = stream
+                .read_value()
+                .map_err(|e| ZipError::map_eof(e, self.offset_of_local_file_header))?;
+            self.file.data_position = file.data_position;
+        }
+        Ok(self.file.data_position)
+    }
+    //重新打包时的本地头位置，覆盖前先按原位置解析源数据中的数据位置
+    pub fn set_offset(&mut self, offset: u64) -> Result<()> {
+        self.data_position()?;
+        self.offset_of_local_file_header = offset;
+        Ok(())
+    }
+    //从源数据中读取条目的原始(压缩)数据
+    pub fn load(&mut self) -> Result<()> {
+        let data_position = self.data_position()?;
+        let Some(source) = &self.source else {
+            return Ok(());
+        };
+        let mut stream = Self::lock_source(source)?;
+        stream.seek(SeekFrom::Start(data_position))?;
+        let data_bytes = stream
+            .read_exact_size(self.compressed_size)
+            .map_err(|e| ZipError::map_eof(e, data_position))?;
+        let mut data: Stream = stream.copy_empty()?;
+        drop(stream);
+        data.write_all(&data_bytes)?;
+        data.seek_start()?;
+        self.data = data;
+        //读取成功后才断开源数据，失败时条目保持未加载
+        self.source = None;
         Ok(())
     }
+    pub fn data(&mut self) -> Result<&mut Stream> {
+        self.load()?;
+        Ok(&mut self.data)
+    }
+    //未加载的数据直接从源数据分块复制，不占用整个条目的内存
+    pub fn copy_source<W: Write>(&mut self, output: &mut W) -> Result<u64> {
+        let data_position = self.data_position()?;
+        let Some(source) = &self.source else {
+            return Ok(0);
+        };
+        let mut stream = Self::lock_source(source)?;
+        stream.seek(SeekFrom::Start(data_position))?;
+        let mut remaining = self.compressed_size;
+        while remaining > 0 {
+            let size = min(remaining, COPY_CHUNK_SIZE);
+            let bytes = stream.read_exact_size(size)?;
+            output.write_all(&bytes)?;
+            remaining -= size;
+        }
+        Ok(self.compressed_size)
+    }
     pub fn put_data(&mut self, stream: Stream) {
-        self.compressed_size = stream.length() as u32;
-        self.uncompressed_size = stream.length() as u32;
+        self.compressed_size = stream.length();
+        self.uncompressed_size = stream.length();
         // if let Some(file) = &mut self.file {
         self.file.compressed_size = self.compressed_size;
         self.file.uncompressed_size = self.uncompressed_size;
         // }
+        self.crc_32_uncompressed_data = 0;
+        self.file.crc_32_uncompressed_data = 0;
         self.compressed = false;
+        self.source = None;
         self.data = stream
     }
     // pub fn put_data_and_compress(
@@ -95,28 +696,301 @@ impl Directory<Parser> {
     //     self.data = stream;
     //     Ok(compress_size)
     // }
+    //切换压缩方式前先解压，已压缩的数据会在打包时按新方式重新处理
+    pub fn set_compression_method(
+        &mut self,
+        compression_method: CompressionMethod,
+    ) -> std::result::Result<(), ZipError> {
+        if self.compression_method != compression_method {
+            codec::check_compress(&self.file.file_name, &compression_method)?;
+            self.decompressed()?;
+            self.compression_method = compression_method.clone();
+            self.file.compression_method = compression_method;
+        }
+        Ok(())
+    }
+    pub fn set_compression_level(&mut self, compression_level: CompressionLevel) {
+        self.compression_level = Some(compression_level);
+    }
+    pub fn set_zstd_level(&mut self, zstd_level: i32) -> std::result::Result<(), ZipError> {
+        codec::check_zstd_level(&self.file_name, zstd_level)?;
+        self.zstd_level = Some(zstd_level);
+        Ok(())
+    }
+    //数据是否能解压，不能解压的条目仍然可以原样重新打包
+    pub fn is_extractable(&self) -> bool {
+        if self.compression_method == CompressionMethod::AES {
+            return cfg!(feature = "aes")
+                && Extra::find_aes(&self.extra_fields)
+                    .or_else(|| Extra::find_aes(&self.file.extra_fields))
+                    .is_some_and(|(_, _, method)| codec::can_decompress(&method));
+        }
+        !self.compressed || codec::can_decompress(&self.compression_method)
+    }
+    pub fn is_encrypted(&self) -> bool {
+        self.flags & ENCRYPTED_FLAG != 0
+    }
+    //读取加密条目时使用的密码
+    pub fn with_password(&mut self, password: &[u8]) {
+        self.password = Some(password.to_vec());
+    }
+    //打包时使用 WinZip AES 加密该条目
+    pub fn with_aes_encryption(&mut self, password: &[u8], strength: AesStrength) {
+        self.password = Some(password.to_vec());
+        self.aes_strength = Some(strength);
+    }
+    fn check_extractable(&self) -> std::result::Result<(), ZipError> {
+        if self.is_extractable() {
+            Ok(())
+        } else {
+            Err(ZipError::UnsupportedMethod {
+                name: self.file_name.clone(),
+                method: self.compression_method.value(),
+            })
+        }
+    }
+    //网页打包时没有计算 crc32 的归档需要关闭校验
+    pub fn with_verify(&mut self, value: bool) {
+        self.verify = value;
+    }
     pub fn decompressed_callback(
         &mut self,
         callback_fun: &mut impl FnMut(usize),
-    ) -> Result<&mut Stream> {
+    ) -> std::result::Result<&mut Stream, ZipError> {
+        self.check_extractable()?;
+        let verify = self.need_verify();
+        self.load()?;
+        self.decrypt()?;
         self.data.seek_start()?;
         if self.compressed {
-            self.data.decompress_callback(callback_fun)?;
+            codec::decompress_callback(
+                &self.file_name,
+                &self.compression_method,
+                &mut self.data,
+                self.flags,
+                self.uncompressed_size,
+                callback_fun,
+            )?;
             self.compressed = false;
         }
+        self.verify_data(verify)?;
         Ok(&mut self.data)
     }
-    pub fn decompressed(&mut self) -> Result<&mut Stream> {
+    pub fn decompressed(&mut self) -> std::result::Result<&mut Stream, ZipError> {
+        self.check_extractable()?;
+        let verify = self.need_verify();
+        self.load()?;
+        self.decrypt()?;
         self.data.seek_start()?;
         if self.compressed {
-            self.data.decompress()?;
+            codec::decompress(
+                &self.file_name,
+                &self.compression_method,
+                &mut self.data,
+                self.flags,
+                self.uncompressed_size,
+            )?;
             self.compressed = false;
         }
+        self.verify_data(verify)?;
         Ok(&mut self.data)
     }
+    //只校验来自归档的数据，返回 (校验大小, 校验 crc32)
+    fn need_verify(&self) -> (bool, bool) {
+        if !self.verify {
+            return (false, false);
+        }
+        self.verify_fields()
+    }
+    fn verify_fields(&self) -> (bool, bool) {
+        if self.is_loaded() && !self.compressed {
+            return (false, false);
+        }
+        //AE-2 不保存 crc32
+        let ae_2 = self.compression_method == CompressionMethod::AES
+            && Extra::find_aes(&self.extra_fields)
+                .or_else(|| Extra::find_aes(&self.file.extra_fields))
+                .is_some_and(|(version, _, _)| version == AE_2);
+        (true, !ae_2)
+    }
+    fn verify_data(&mut self, verify: (bool, bool)) -> std::result::Result<(), ZipError> {
+        let actual_crc32 = if verify.1 {
+            self.data.init_crc32();
+            self.data.hash_computer()?;
+            let crc32 = self.data.crc32_value();
+            self.data.seek_start()?;
+            crc32
+        } else {
+            0
+        };
+        self.check_sums(verify, self.data.length(), actual_crc32)
+    }
+    fn check_sums(
+        &self,
+        (size, crc32): (bool, bool),
+        actual_size: u64,
+        actual_crc32: u32,
+    ) -> std::result::Result<(), ZipError> {
+        if size && actual_size != self.uncompressed_size {
+            return Err(ZipError::SizeMismatch {
+                name: self.file_name.clone(),
+                expected: self.uncompressed_size,
+                actual: actual_size,
+            });
+        }
+        if crc32 && actual_crc32 != self.crc_32_uncompressed_data {
+            return Err(ZipError::ChecksumMismatch {
+                name: self.file_name.clone(),
+                expected: self.crc_32_uncompressed_data,
+                actual: actual_crc32,
+            });
+        }
+        Ok(())
+    }
+    //流式解密、解压后只计算 crc32 和大小，不保留解压结果
+    pub fn test(&mut self) -> std::result::Result<(), ZipError> {
+        self.check_local_file()?;
+        self.check_extractable()?;
+        let data_position = self.data_position()?;
+        let mut sink = ChecksumWriter {
+            crc32: Crc32::new(),
+            size: 0,
+        };
+        match &self.source {
+            Some(source) => {
+                let mut stream = Self::lock_source(source)?;
+                stream.seek(SeekFrom::Start(data_position))?;
+                self.test_data(&mut *stream, &mut sink)?;
+            }
+            None => {
+                let mut data = std::mem::replace(&mut self.data, Stream::empty());
+                let result = data
+                    .seek_start()
+                    .map_err(ZipError::from)
+                    .and_then(|_| self.test_data(&mut data, &mut sink));
+                self.data = data;
+                self.data.seek_start()?;
+                result?;
+            }
+        }
+        self.check_sums(self.verify_fields(), sink.size, sink.crc32.value())
+    }
+    fn test_data(
+        &self,
+        input: &mut dyn Read,
+        sink: &mut ChecksumWriter,
+    ) -> std::result::Result<(), ZipError> {
+        let position = self.file.data_position;
+        let input = input.take(self.compressed_size);
+        if self.compression_method == CompressionMethod::AES {
+            let (password, strength, compression_method) = self.aes_params()?;
+            let mut reader = AesReader::new(
+                &self.file_name,
+                position,
+                input,
+                password,
+                strength,
+                self.compressed_size,
+            )?;
+            let result = self.decode_data(&compression_method, &mut reader, sink);
+            //认证码不匹配时数据不可信，优先报告
+            reader.finish()?;
+            result
+        } else if self.is_encrypted() {
+            let (password, check_byte) = self.zip_crypto_params()?;
+            let reader =
+                ZipCryptoReader::new(&self.file_name, position, input, password, check_byte)?;
+            self.decode_data(&self.compression_method, reader, sink)
+        } else {
+            self.decode_data(&self.compression_method, input, sink)
+        }
+    }
+    fn decode_data(
+        &self,
+        method: &CompressionMethod,
+        input: impl Read,
+        sink: &mut ChecksumWriter,
+    ) -> std::result::Result<(), ZipError> {
+        let method = if self.compressed {
+            method
+        } else {
+            &CompressionMethod::Store
+        };
+        let mut decoder = codec::decoder(
+            &self.file_name,
+            method,
+            Box::new(input),
+            self.flags,
+            self.uncompressed_size,
+        )?;
+        std::io::copy(&mut decoder, sink)?;
+        Ok(())
+    }
+    fn check_local_file(&self) -> std::result::Result<(), ZipError> {
+        let Some(source) = &self.source else {
+            return Ok(());
+        };
+        let mut stream = Self::lock_source(source)?;
+        stream.seek(SeekFrom::Start(self.offset_of_local_file_header))?;
+        let file: ZipFile<Parser> = stream
+            .read_value()
+            .map_err(|e| ZipError::map_eof(e, self.offset_of_local_file_header))?;
+        file.check_file_name(&self.file_name, &self.file_name_bytes())?;
+        file.check_central(
+            &self.file_name,
+            &self.compression_method,
+            self.flags,
+            self.crc_32_uncompressed_data,
+            self.compressed_size,
+            self.uncompressed_size,
+        )?;
+        //流式条目真实的 crc 和大小在数据后面的 DataDescriptor 中
+        if file.flags & 0x0008 != 0 {
+            let (zip64_uncompressed_size, zip64_compressed_size, _) =
+                Extra::zip64_fields(&file.extra_fields);
+            let position = file.data_position + self.compressed_size;
+            stream.seek(SeekFrom::Start(position))?;
+            let data_descriptor = DataDescriptor::read_with(
+                &mut stream,
+                zip64_uncompressed_size || zip64_compressed_size,
+            )
+            .map_err(|e| ZipError::map_eof(e, position))?;
+            let mismatch = |field| ZipError::LocalHeaderMismatch {
+                name: self.file_name.clone(),
+                field,
+            };
+            if data_descriptor.crc32 != self.crc_32_uncompressed_data {
+                return Err(mismatch("data descriptor crc32"));
+            }
+            if data_descriptor.compressed_size != self.compressed_size
+                || data_descriptor.uncompressed_size != self.uncompressed_size
+            {
+                return Err(mismatch("data descriptor size"));
+            }
+        }
+        Ok(())
+    }
+}
+
+//写在 ZIP64 字段中的值头部统一写 0xFFFFFFFF
+pub(crate) fn zip64_value(in_zip64: bool, value: u64) -> u32 {
+    if in_zip64 { u32::MAX } else { value as u32 }
+}
+//头部中的长度字段只有 16 位
+pub(crate) fn u16_length(name: &str, field: &'static str, length: usize) -> Result<u16> {
+    u16::try_from(length).map_err(|_| {
+        ZipError::LimitExceeded {
+            name: name.to_string(),
+            field,
+            value: length as u64,
+            limit: u16::MAX as u64,
+        }
+        .into()
+    })
 }
 
-const DIRECTORY_HEADER_SIZE: usize = Magic::byte_size()
+//中央目录记录的最小长度
+pub(crate) const DIRECTORY_HEADER_SIZE: usize = Magic::byte_size()
     + size_of::<u16>() * 6
     + size_of::<u32>() * 3
     + size_of::<u16>() * 5
@@ -124,7 +998,9 @@ const DIRECTORY_HEADER_SIZE: usize = Magic::byte_size()
 #[derive(Debug, Clone)]
 pub struct Directory<TYPE> {
     pub r#type: TYPE,
-    pub data: Stream,
+    //未加载的条目为空，通过 data() 或 load() 读取
+    pub(crate) data: Stream,
+    pub(crate) source: Option<Arc<Mutex<Stream>>>,
     pub compressed: bool,
     pub created_zip_spec: u8,
     pub created_os: u8,
@@ -132,28 +1008,42 @@ pub struct Directory<TYPE> {
     pub extract_os: u8,
     pub flags: u16,
     pub compression_method: CompressionMethod,
+    //None 时使用 Zip 的 compression_level
+    pub compression_level: Option<CompressionLevel>,
     pub last_modification_time: u16,
     pub last_modification_date: u16,
     pub crc_32_uncompressed_data: u32,
-    pub compressed_size: u32,
-    pub uncompressed_size: u32,
+    pub compressed_size: u64,
+    pub uncompressed_size: u64,
     pub file_name_length: u16,
     pub extra_field_length: u16,
     pub file_comment_length: u16,
     pub number_of_starts: u16,
     pub internal_file_attributes: u16,
     pub external_file_attributes: u32,
-    pub offset_of_local_file_header: u32,
+    pub offset_of_local_file_header: u64,
     pub file_name: String,
+    pub raw_file_name: Option<RawFileName>,
     pub extra_fields: Vec<Extra>,
     pub file_comment: Vec<u8>,
     pub file: ZipFile<TYPE>,
+    //解密读取和加密写入使用的密码
+    pub(crate) password: Option<Vec<u8>>,
+    //设置后打包时使用 WinZip AES 加密
+    pub(crate) aes_strength: Option<AesStrength>,
+    //Zstd 压缩的级别，None 时按 compression_level 换算
+    pub(crate) zstd_level: Option<i32>,
+    //解压后校验 crc32 和大小
+    pub(crate) verify: bool,
+    //未显式指定压缩方式时才允许自适应改为 Store
+    pub(crate) adaptive: bool,
 }
 impl Directory<Parser> {
     pub fn clone_all(&mut self) -> Result<Self> {
         Ok(Directory {
             r#type: Parser,
             data: self.data.clone_stream()?,
+            source: self.source.clone(),
             compressed: self.compressed,
             created_zip_spec: self.created_zip_spec,
             created_os: self.created_os,
@@ -161,6 +1051,7 @@ impl Directory<Parser> {
             extract_os: self.extract_os,
             flags: self.flags,
             compression_method: self.compression_method.clone(),
+            compression_level: self.compression_level.clone(),
             last_modification_time: self.last_modification_time,
             last_modification_date: self.last_modification_date,
             crc_32_uncompressed_data: self.crc_32_uncompressed_data,
@@ -174,15 +1065,22 @@ impl Directory<Parser> {
             external_file_attributes: self.external_file_attributes,
             offset_of_local_file_header: self.offset_of_local_file_header,
             file_name: self.file_name.clone(),
+            raw_file_name: self.raw_file_name.clone(),
             extra_fields: self.extra_fields.clone(),
             file_comment: self.file_comment.clone(),
             file: self.file.clone(),
+            password: self.password.clone(),
+            aes_strength: self.aes_strength,
+            zstd_level: self.zstd_level,
+            verify: self.verify,
+            adaptive: self.adaptive,
         })
     }
     pub fn clone_not_stream(&self) -> Self {
         Directory {
             r#type: Parser,
             data: Stream::empty(),
+            source: None,
             compressed: self.compressed,
             created_zip_spec: self.created_zip_spec,
             created_os: self.created_os,
@@ -190,6 +1088,7 @@ impl Directory<Parser> {
             extract_os: self.extract_os,
             flags: self.flags,
             compression_method: self.compression_method.clone(),
+            compression_level: self.compression_level.clone(),
             last_modification_time: self.last_modification_time,
             last_modification_date: self.last_modification_date,
             crc_32_uncompressed_data: self.crc_32_uncompressed_data,
@@ -203,19 +1102,222 @@ impl Directory<Parser> {
             external_file_attributes: self.external_file_attributes,
             offset_of_local_file_header: self.offset_of_local_file_header,
             file_name: self.file_name.clone(),
+            raw_file_name: self.raw_file_name.clone(),
             extra_fields: self.extra_fields.clone(),
             file_comment: self.file_comment.clone(),
             file: self.file.clone(),
+            password: self.password.clone(),
+            aes_strength: self.aes_strength,
+            zstd_level: self.zstd_level,
+            verify: self.verify,
+            adaptive: self.adaptive,
         }
     }
 }
 impl Directory<Parser> {
+    //超过 32 位限制(或 force)时写入 ZIP64 字段，返回是否使用了 ZIP64
+    pub fn update_zip64(&mut self, force: bool) -> bool {
+        let limit = u32::MAX as u64;
+        let uncompressed = force || self.uncompressed_size >= limit;
+        let compressed = force || self.compressed_size >= limit;
+        let offset = force || self.offset_of_local_file_header >= limit;
+        self.extra_fields
+            .retain(|extra_field| !matches!(extra_field, Extra::Zip64 { .. }));
+        self.file
+            .extra_fields
+            .retain(|extra_field| !matches!(extra_field, Extra::Zip64 { .. }));
+        if !uncompressed && !compressed && !offset {
+            return false;
+        }
+        self.extra_fields.insert(
+            0,
+            Extra::Zip64 {
+                uncompressed_size: uncompressed.then_some(self.uncompressed_size),
+                compressed_size: compressed.then_some(self.compressed_size),
+                offset: offset.then_some(self.offset_of_local_file_header),
+                disk: None,
+            },
+        );
+        if uncompressed || compressed {
+            self.file.extra_fields.insert(
+                0,
+                Extra::Zip64 {
+                    uncompressed_size: Some(self.uncompressed_size),
+                    compressed_size: Some(self.compressed_size),
+                    offset: None,
+                    disk: None,
+                },
+            );
+        }
+        self.created_zip_spec = max(self.created_zip_spec, ZIP64_ZIP_SPEC as u8);
+        self.extract_zip_spec = max(self.extract_zip_spec, ZIP64_ZIP_SPEC as u8);
+        true
+    }
+    //同步文件名长度，旧编码的文件名和注释附带 Unicode 扩展字段
+    pub fn update_file_name(&mut self) {
+        let comment = self.comment();
+        let (file_name, flags) =
+            RawFileName::encode(&self.file_name, &self.raw_file_name, self.flags);
+        Extra::update_unicode_path(&mut self.extra_fields, &self.file_name, &file_name, flags);
+        Extra::update_unicode_comment(&mut self.extra_fields, &comment, &self.file_comment, flags);
+        self.file_name_length = file_name.len() as u16;
+        let file = &mut self.file;
+        let (file_name, flags) =
+            RawFileName::encode(&file.file_name, &file.raw_file_name, file.flags);
+        Extra::update_unicode_path(&mut file.extra_fields, &file.file_name, &file_name, flags);
+        file.file_name_length = file_name.len() as u16;
+    }
+    pub fn update_version_needed(&mut self) {
+        let version_needed = self.compression_method.version_needed();
+        self.extract_zip_spec = max(self.extract_zip_spec, version_needed);
+        self.file.extract_zip_spec = max(self.file.extract_zip_spec, version_needed);
+        self.created_zip_spec = max(self.created_zip_spec, version_needed);
+    }
+    //保持本地头的 bit 3 和 DataDescriptor 与中央目录一致
+    pub fn update_data_descriptor(&mut self) {
+        let (zip64_uncompressed_size, zip64_compressed_size, _) =
+            Extra::zip64_fields(&self.file.extra_fields);
+        if let Some(data_descriptor) = &mut self.file.data_descriptor {
+            data_descriptor.crc32 = self.crc_32_uncompressed_data;
+            data_descriptor.compressed_size = self.compressed_size;
+            data_descriptor.uncompressed_size = self.uncompressed_size;
+            data_descriptor.zip64 = zip64_uncompressed_size || zip64_compressed_size;
+            self.flags |= 0x0008;
+            self.file.flags |= 0x0008;
+        } else {
+            self.flags &= !0x0008;
+            self.file.flags &= !0x0008;
+        }
+    }
+    //写入时使用的文件名字节
+    pub fn file_name_bytes(&self) -> Vec<u8> {
+        RawFileName::encode(&self.file_name, &self.raw_file_name, self.flags).0
+    }
+    //同时更新 DOS 时间、扩展时间戳和 NTFS 时间
+    pub fn set_modified(&mut self, time: SystemTime) {
+        let (seconds, nanos) = datetime::unix_time(time);
+        let (last_modification_time, last_modification_date) = datetime::to_dos(seconds);
+        self.last_modification_time = last_modification_time;
+        self.last_modification_date = last_modification_date;
+        self.file.last_modification_time = last_modification_time;
+        self.file.last_modification_date = last_modification_date;
+        let unix_mtime = seconds.clamp(i32::MIN as i64, i32::MAX as i64) as i32;
+        let ntfs_mtime = datetime::to_ntfs(seconds, nanos);
+        for extra_fields in [&mut self.extra_fields, &mut self.file.extra_fields] {
+            let mut has_timestamp = false;
+            for extra_field in extra_fields.iter_mut() {
+                match extra_field {
+                    Extra::UnixExtendedTimestamp { mtime, .. } => {
+                        *mtime = Some(unix_mtime);
+                        has_timestamp = true;
+                    }
+                    Extra::NTFS { mtime, .. } => *mtime = ntfs_mtime,
+                    _ => {}
+                }
+            }
+            if !has_timestamp {
+                extra_fields.push(Extra::UnixExtendedTimestamp {
+                    mtime: Some(unix_mtime),
+                    atime: None,
+                    ctime: None,
+                });
+            }
+        }
+    }
+    pub fn set_modified_unix(&mut self, seconds: i64) {
+        self.set_modified(datetime::system_time(seconds, 0));
+    }
+    pub fn modified(&self) -> SystemTime {
+        Extra::find_modified(
+            &self.extra_fields,
+            self.last_modification_time,
+            self.last_modification_date,
+        )
+    }
+    //created_os 为 Unix 时高 16 位才是 st_mode
+    pub fn unix_mode(&self) -> Option<u32> {
+        let mode = self.external_file_attributes >> 16;
+        if self.created_os == OS_UNIX && mode != 0 {
+            Some(mode)
+        } else {
+            None
+        }
+    }
+    //同步 DOS 的目录和只读位，并把 created_os 置为 Unix
+    pub fn set_unix_mode(&mut self, mode: u32) {
+        let mode = mode & 0xFFFF;
+        let dos_attributes = (self.dos_attributes() & !(DOS_DIRECTORY | DOS_READ_ONLY))
+            | dos_attributes_for_mode(mode);
+        self.external_file_attributes = external_attributes(mode, dos_attributes);
+        self.created_os = OS_UNIX;
+    }
+    pub fn permissions(&self) -> Option<u32> {
+        self.unix_mode().map(|mode| mode & PERMISSIONS_MASK)
+    }
+    pub fn set_permissions(&mut self, permissions: u32) {
+        let file_type = self.file_type();
+        self.set_unix_mode(file_type.mode_bits() | (permissions & PERMISSIONS_MASK));
+    }
+    pub fn is_executable(&self) -> bool {
+        self.permissions()
+            .is_some_and(|permissions| permissions & EXECUTABLE_MASK != 0)
+    }
+    //没有 Unix 模式时按 DOS 属性和文件名判断
+    pub fn file_type(&self) -> FileType {
+        if let Some(file_type) = self.unix_mode().and_then(FileType::from_mode) {
+            return file_type;
+        }
+        if self.dos_attributes() & DOS_DIRECTORY != 0 || self.file_name.ends_with('/') {
+            FileType::Directory
+        } else {
+            FileType::Regular
+        }
+    }
+    pub fn set_file_type(&mut self, file_type: FileType) {
+        let permissions = self.permissions().unwrap_or(match file_type {
+            FileType::Regular => DEFAULT_FILE_MODE,
+            FileType::Directory => DEFAULT_DIR_MODE,
+            FileType::Symlink => DEFAULT_SYMLINK_MODE,
+        } & PERMISSIONS_MASK);
+        self.set_unix_mode(file_type.mode_bits() | permissions);
+    }
+    pub fn is_dir(&self) -> bool {
+        self.file_type() == FileType::Directory
+    }
+    pub fn is_symlink(&self) -> bool {
+        self.file_type() == FileType::Symlink
+    }
+    //符号链接的目标保存在条目数据中，读取时不改变条目本身的数据
+    pub fn symlink_target(&mut self) -> std::result::Result<Option<String>, ZipError> {
+        if !self.is_symlink() {
+            return Ok(None);
+        }
+        let mut entry = self.clone_all()?;
+        let target = entry.decompressed()?.take_data()?;
+        String::from_utf8(target)
+            .map(Some)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e).into())
+    }
+    pub fn dos_attributes(&self) -> u8 {
+        self.external_file_attributes as u8
+    }
+    pub fn set_dos_attributes(&mut self, attributes: u8) {
+        self.external_file_attributes =
+            (self.external_file_attributes & !0xFF) | attributes as u32;
+    }
+    //Unicode Comment 有效时优先，否则按 flags 解码
+    pub fn comment(&self) -> String {
+        match Extra::find_unicode_comment(&self.extra_fields, &self.file_comment) {
+            Some(comment) => comment,
+            None => FileNameEncoding::Auto.decode(self.flags, &self.file_comment),
+        }
+    }
     pub fn compressed(&self) -> bool {
         self.compressed
     }
     pub fn size(&self) -> usize {
         let mut bytes =
-            DIRECTORY_HEADER_SIZE + self.file_name.as_bytes().len() + self.file_comment.len();
+            DIRECTORY_HEADER_SIZE + self.file_name_bytes().len() + self.file_comment.len();
         for extra_field in &self.extra_fields {
             bytes += extra_field.size() as usize
         }
@@ -226,25 +1328,32 @@ impl ValueWrite for Directory<Parser> {
     fn write(mut self, endian: &Endian) -> Result<Stream> {
         let mut stream = Stream::empty();
         stream.with_endian(endian.clone());
-        let compression_method = if self.uncompressed_size == 0 {
+        let compression_method = if self.uncompressed_size == 0
+            && self.compression_method != CompressionMethod::AES
+        {
             CompressionMethod::Store
         } else {
             self.compression_method
         };
-        self.flags = 0;
 
+        let (zip64_uncompressed_size, zip64_compressed_size, zip64_offset) =
+            Extra::zip64_fields(&self.extra_fields);
         let mut extra_field_stream = Stream::empty();
-        self.extra_field_length = 0;
+        let mut extra_field_length = 0;
         for extra_field in self.extra_fields {
-            self.extra_field_length += extra_field.size();
+            extra_field_length += extra_field.size() as usize;
             extra_field_stream.write_value(extra_field)?;
         }
         extra_field_stream.seek_start()?;
+        self.extra_field_length = u16_length(&self.file_name, "extra field", extra_field_length)?;
 
-        self.file_comment_length = self.file_comment.len() as u16;
-        self.file_name_length = self.file_name.as_bytes().len() as u16;
+        self.file_comment_length =
+            u16_length(&self.file_name, "file comment", self.file_comment.len())?;
+        let (file_name, flags) =
+            RawFileName::encode(&self.file_name, &self.raw_file_name, self.flags);
+        self.flags = flags;
+        self.file_name_length = u16_length(&self.file_name, "file name", file_name.len())?;
 
-        //禁止流式格式
         stream.write_value(Magic::Directory)?;
         stream.write_value(self.created_zip_spec)?;
         stream.write_value(self.created_os)?;
@@ -255,115 +1364,210 @@ impl ValueWrite for Directory<Parser> {
         stream.write_value(self.last_modification_time)?;
         stream.write_value(self.last_modification_date)?;
         stream.write_value(self.crc_32_uncompressed_data)?;
-        stream.write_value(self.compressed_size)?;
-        stream.write_value(self.uncompressed_size)?;
+        stream.write_value(zip64_value(zip64_compressed_size, self.compressed_size))?;
+        stream.write_value(zip64_value(zip64_uncompressed_size, self.uncompressed_size))?;
         stream.write_value(self.file_name_length)?;
         stream.write_value(self.extra_field_length)?;
         stream.write_value(self.file_comment_length)?;
         stream.write_value(self.number_of_starts)?;
         stream.write_value(self.internal_file_attributes)?;
         stream.write_value(self.external_file_attributes)?;
-        stream.write_value(self.offset_of_local_file_header)?;
-        stream.write(self.file_name.as_bytes())?;
+        stream.write_value(zip64_value(zip64_offset, self.offset_of_local_file_header))?;
+        stream.write_all(&file_name)?;
         stream.append(&mut extra_field_stream)?;
-        stream.write(&self.file_comment)?;
+        stream.write_all(&self.file_comment)?;
         Ok(stream)
     }
 }
 impl ValueRead for Directory<Parser> {
     fn read(stream: &mut Stream) -> Result<Self> {
-        let magic: Magic = stream.read_value()?;
-        if magic != Magic::Directory {
-            return Err(Error::new(
-                ErrorKind::InvalidData,
-                "Invalid directory magic number",
-            ));
-        }
-        let created_zip_spec: u8 = stream.read_value()?;
-        let created_os: u8 = stream.read_value()?;
-        let extract_zip_spec: u8 = stream.read_value()?;
-        let extract_os: u8 = stream.read_value()?;
-        let flags: u16 = stream.read_value()?;
-        let compression_method: CompressionMethod = stream.read_value()?;
-        let last_modification_time: u16 = stream.read_value()?;
-        let last_modification_date: u16 = stream.read_value()?;
-        let crc_32_uncompressed_data: u32 = stream.read_value()?;
-        let compressed_size: u32 = stream.read_value()?;
-        let uncompressed_size: u32 = stream.read_value()?;
-        let file_name_length: u16 = stream.read_value()?;
-        let extra_field_length: u16 = stream.read_value()?;
-        let file_comment_length: u16 = stream.read_value()?;
-        let number_of_starts: u16 = stream.read_value()?;
-        let internal_file_attributes: u16 = stream.read_value()?;
-        let external_file_attributes: u32 = stream.read_value()?;
-        let offset_of_local_file_header: u32 = stream.read_value()?;
-        let mut extra_fields: Vec<Extra> = Vec::new();
-        let file_name = stream.read_exact_size(file_name_length as u64)?;
-        let file_name =
-            String::from_utf8(file_name).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
-        let compressed = compression_method == CompressionMethod::Deflate;
-        let mut total_bytes = 0;
-        if extra_field_length > 0 {
-            loop {
-                let position = stream.position()?;
-                let extra_field: Extra = stream.read_value()?;
-                extra_fields.push(extra_field);
-                let size = stream.position()? - position;
-                total_bytes += size;
-                if total_bytes >= extra_field_length as u64 {
-                    break;
-                }
-            }
-        }
-        let file_comment = stream.read_exact_size(file_comment_length as u64)?;
-        stream.pin()?;
-        stream.seek(SeekFrom::Start(offset_of_local_file_header as u64))?;
-        let mut file: ZipFile<Parser> = stream.read_value()?;
-        file.compressed_size = max(compressed_size, file.uncompressed_size);
-        file.uncompressed_size = max(uncompressed_size, file.uncompressed_size);
-        file.crc_32_uncompressed_data =
-            max(crc_32_uncompressed_data, file.crc_32_uncompressed_data);
-        if file.flags & 0x0008 != 0 {
-            //TODO数据是流式的
-            file.flags = 0; // 强转非流式
-            // file.data_descriptor = Some(DataDescriptor {
-            //     crc32: crc_32_uncompressed_data,
-            //     compressed_size,
-            //     uncompressed_size,
-            // })
-        }
-        stream.seek(SeekFrom::Start(file.data_position))?;
-        let data_bytes = stream.read_exact_size(compressed_size as u64)?;
-        let mut data: Stream = stream.copy_empty()?;
-        data.write_all(&data_bytes)?;
-        data.seek_start()?;
-        stream.un_pin()?;
+        Self::read_with(stream, &FileNameEncoding::default())
+    }
+}
+impl Directory<Parser> {
+    //文件名按指定的编码解码
+    pub fn read_with(stream: &mut Stream, encoding: &FileNameEncoding) -> Result<Self> {
+        let info = EntryInfo::read_with(stream, encoding)?;
+        let compressed = codec::is_compressed(&info.compression_method);
+        //解析时只读取中央目录，本地头在读取数据时才读取
+        let file = info.to_local_file();
         Ok(Self {
             r#type: Parser,
             compressed,
-            data,
-            created_zip_spec,
-            created_os,
-            extract_zip_spec,
-            extract_os,
-            flags,
-            compression_method,
-            last_modification_time,
-            last_modification_date,
-            crc_32_uncompressed_data,
-            compressed_size,
-            uncompressed_size,
-            file_name_length,
-            extra_field_length,
-            file_comment_length,
-            number_of_starts,
-            internal_file_attributes,
-            external_file_attributes,
-            offset_of_local_file_header,
-            file_name,
-            extra_fields,
-            file_comment,
+            data: Stream::empty(), //数据在使用时才从 source 中读取
+            source: None,
+            created_zip_spec: info.created_zip_spec,
+            created_os: info.created_os,
+            extract_zip_spec: info.extract_zip_spec,
+            extract_os: info.extract_os,
+            flags: info.flags,
+            compression_method: info.compression_method,
+            compression_level: None,
+            last_modification_time: info.last_modification_time,
+            last_modification_date: info.last_modification_date,
+            crc_32_uncompressed_data: info.crc_32_uncompressed_data,
+            compressed_size: info.compressed_size,
+            uncompressed_size: info.uncompressed_size,
+            file_name_length: info.file_name_length,
+            extra_field_length: info.extra_field_length,
+            file_comment_length: info.file_comment_length,
+            number_of_starts: info.number_of_starts,
+            internal_file_attributes: info.internal_file_attributes,
+            external_file_attributes: info.external_file_attributes,
+            offset_of_local_file_header: info.offset_of_local_file_header,
+            file_name: info.file_name,
+            raw_file_name: info.raw_file_name,
+            extra_fields: info.extra_fields,
+            file_comment: info.file_comment,
             file,
+            verify: true,
+            adaptive: false,
+            password: None,
+            aes_strength: None,
+            zstd_level: None,
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::zip::Zip;
+
+    //Python 生成，a.txt 是 Reduce(2)，b.txt 是 LZ77(19)，数据不能解压
+    const UNKNOWN_METHOD_ARCHIVE: [u8; 215] = [
+        0x50, 0x4b, 0x03, 0x04, 0x14, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x60, 0x21, 0x5a, 0x83,
+        0x16, 0xdc, 0x8c, 0x0c, 0x00, 0x00, 0x00, 0x64, 0x00, 0x00, 0x00, 0x05, 0x00, 0x00, 0x00,
+        0x61, 0x2e, 0x74, 0x78, 0x74, 0x72, 0x65, 0x64, 0x75, 0x63, 0x65, 0x64, 0x20, 0x64, 0x61,
+        0x74, 0x61, 0x50, 0x4b, 0x03, 0x04, 0x14, 0x00, 0x00, 0x00, 0x13, 0x00, 0x00, 0x60, 0x21,
+        0x5a, 0x83, 0x16, 0xdc, 0x8c, 0x09, 0x00, 0x00, 0x00, 0x64, 0x00, 0x00, 0x00, 0x05, 0x00,
+        0x00, 0x00, 0x62, 0x2e, 0x74, 0x78, 0x74, 0x6c, 0x7a, 0x37, 0x37, 0x20, 0x64, 0x61, 0x74,
+        0x61, 0x50, 0x4b, 0x01, 0x02, 0x14, 0x00, 0x14, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x60,
+        0x21, 0x5a, 0x83, 0x16, 0xdc, 0x8c, 0x0c, 0x00, 0x00, 0x00, 0x64, 0x00, 0x00, 0x00, 0x05,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x61, 0x2e, 0x74, 0x78, 0x74, 0x50, 0x4b, 0x01, 0x02, 0x14, 0x00, 0x14, 0x00,
+        0x00, 0x00, 0x13, 0x00, 0x00, 0x60, 0x21, 0x5a, 0x83, 0x16, 0xdc, 0x8c, 0x09, 0x00, 0x00,
+        0x00, 0x64, 0x00, 0x00, 0x00, 0x05, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x2f, 0x00, 0x00, 0x00, 0x62, 0x2e, 0x74, 0x78, 0x74, 0x50, 0x4b,
+        0x05, 0x06, 0x00, 0x00, 0x00, 0x00, 0x02, 0x00, 0x02, 0x00, 0x66, 0x00, 0x00, 0x00, 0x5b,
+        0x00, 0x00, 0x00, 0x00, 0x00,
+    ];
+
+    fn archive(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut zip = Zip::create(Stream::empty());
+        zip.with_crc32(true);
+        for (name, data) in files {
+            let data = Stream::new(data.to_vec().into());
+            zip.add_file(data, name).unwrap();
+        }
+        let mut output = Stream::empty();
+        zip.package(&mut output, &mut |_, _, _| {}).unwrap();
+        output.take_data().unwrap()
+    }
+    fn entry_data(zip: &mut Zip<Parser>, name: &str) -> Vec<u8> {
+        let directory = zip.directory(name).unwrap();
+        directory.decompressed().unwrap().take_data().unwrap()
+    }
+
+    #[test]
+    fn parse_reads_only_central_directory() {
+        let mut data = archive(&[("a.txt", b"hello hello hello hello")]);
+        //本地头签名损坏时解析仍然成功，读取数据时才报告
+        data[0] = 0;
+        let mut zip = Zip::new(Stream::new(data.into())).unwrap();
+        let directory = zip.directory("a.txt").unwrap();
+        assert!(matches!(
+            directory.decompressed(),
+            Err(ZipError::BadSignature { offset: 0, .. })
+        ));
+    }
+    #[test]
+    fn parse_keeps_central_sizes() {
+        let plain = b"central directory sizes\n".repeat(100);
+        let data = archive(&[("a.txt", &plain)]);
+        let mut zip = Zip::new(Stream::new(data.into())).unwrap();
+        let directory = zip.directory("a.txt").unwrap();
+        assert!(directory.compressed_size < directory.uncompressed_size);
+        assert_eq!(directory.file.compressed_size, directory.compressed_size);
+        assert_eq!(entry_data(&mut zip, "a.txt"), plain);
+    }
+    #[test]
+    fn rewrite_uses_central_fields() {
+        let plain = b"rewrite without reading\n".repeat(100);
+        let data = archive(&[("a.txt", &plain), ("b.txt", b"b")]);
+        let mut zip = Zip::new(Stream::new(data.into())).unwrap();
+        let mut output = Stream::empty();
+        zip.package(&mut output, &mut |_, _, _| {}).unwrap();
+        let mut zip = Zip::new(output).unwrap();
+        assert!(zip.test().iter().all(|test| test.is_ok()));
+        assert_eq!(entry_data(&mut zip, "a.txt"), plain);
+    }
+    #[test]
+    fn checksum_mismatch() {
+        let plain = b"checksum mismatch\n".repeat(100);
+        let mut data = archive(&[("a.txt", &plain)]);
+        //本地头和中央目录的 crc32 同时改写，比对本地头时不报错
+        let eocd = data.len() - 22;
+        let central = u32::from_le_bytes(data[eocd + 16..eocd + 20].try_into().unwrap()) as usize;
+        let crc32 = u32::from_le_bytes(data[14..18].try_into().unwrap());
+        for offset in [14, central + 16] {
+            data[offset..offset + 4].copy_from_slice(&(crc32 ^ 1).to_le_bytes());
+        }
+        let mut zip = Zip::new(Stream::new(data.clone().into())).unwrap();
+        let directory = zip.directory("a.txt").unwrap();
+        let error = directory.decompressed().err().unwrap();
+        assert!(matches!(
+            error,
+            ZipError::ChecksumMismatch { expected, actual, .. }
+                if expected == crc32 ^ 1 && actual == crc32
+        ));
+
+        let mut zip = Zip::new(Stream::new(data.into())).unwrap();
+        zip.with_verify(false);
+        assert_eq!(entry_data(&mut zip, "a.txt"), plain);
+    }
+    #[test]
+    fn load_failure_keeps_source() {
+        let mut data = archive(&[("a.txt", b"hello")]);
+        //中央目录中的压缩大小超出归档，读取数据时截断
+        let eocd = data.len() - 22;
+        let central = u32::from_le_bytes(data[eocd + 16..eocd + 20].try_into().unwrap()) as usize;
+        data[central + 20..central + 24].copy_from_slice(&0x10000_u32.to_le_bytes());
+        let mut zip = Zip::new(Stream::new(data.into())).unwrap();
+        let directory = zip.directory("a.txt").unwrap();
+        for _ in 0..2 {
+            let error = ZipError::from(directory.load().unwrap_err());
+            assert!(matches!(error, ZipError::Truncated { .. }));
+            assert!(!directory.is_loaded());
+        }
+    }
+    #[test]
+    fn unknown_method_round_trip() {
+        assert_eq!(CompressionMethod::from(18), CompressionMethod::Unknown(18));
+        assert_eq!(CompressionMethod::from(0x63), CompressionMethod::AES);
+        assert_eq!(u16::from(CompressionMethod::Unknown(7)), 7);
+
+        let mut zip = Zip::new(Stream::new(UNKNOWN_METHOD_ARCHIVE.to_vec().into())).unwrap();
+        for (name, value) in [("a.txt", 2), ("b.txt", 19)] {
+            let directory = zip.directory(name).unwrap();
+            let method = CompressionMethod::Unknown(value);
+            assert_eq!(directory.compression_method, method);
+            assert!(!directory.is_extractable());
+            let error = directory.decompressed().err().unwrap();
+            assert!(matches!(error, ZipError::UnsupportedMethod { method, .. } if method == value));
+        }
+        //不能解压的条目原样复制
+        let mut output = Stream::empty();
+        zip.package(&mut output, &mut |_, _, _| {}).unwrap();
+        let data = output.take_data().unwrap();
+        assert_eq!(data[8..10], [2, 0]);
+        assert_eq!(data[30..47], *b"a.txtreduced data");
+
+        let mut zip = Zip::new(Stream::new(data.into())).unwrap();
+        let directory = zip.directory("b.txt").unwrap();
+        assert_eq!(directory.compression_method, CompressionMethod::Unknown(19));
+        assert_eq!(directory.uncompressed_size, 100);
+        let data = directory.data().unwrap().take_data().unwrap();
+        assert_eq!(data, b"lz77 data");
+    }
+}
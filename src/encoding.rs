@@ -0,0 +1,218 @@
+use crate::error::ZipError;
+use crate::extra::Extra;
+use fast_stream::bytes::{Bytes, ValueRead, ValueWrite};
+use fast_stream::endian::Endian;
+use fast_stream::stream::Stream;
+use std::io::Result;
+
+//general purpose bit 11，文件名和注释为 UTF-8
+pub const UTF8_FLAG: u16 = 0x0800;
+
+//CP437 0x80..=0xFF 对应的字符，低位与 ASCII 相同
+const CP437_HIGH: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç',
+    'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å',
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù',
+    'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ',
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º',
+    '¿', '⌐', '¬', '½', '¼', '¡', '«', '»',
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖',
+    '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐',
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟',
+    '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧',
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫',
+    '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀',
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ',
+    'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩',
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈',
+    '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{a0}',
+];
+
+#[derive(Debug, Clone, Default)]
+pub enum FileNameEncoding {
+    //合法 UTF-8 按 UTF-8，否则按 CP437
+    #[default]
+    Auto,
+    Cp437,
+    Custom(fn(&[u8]) -> String),
+}
+impl FileNameEncoding {
+    pub fn decode(&self, flags: u16, bytes: &[u8]) -> String {
+        if flags & UTF8_FLAG != 0 || bytes.is_ascii() {
+            return String::from_utf8_lossy(bytes).into_owned();
+        }
+        match self {
+            FileNameEncoding::Auto => match std::str::from_utf8(bytes) {
+                Ok(name) => name.to_string(),
+                Err(_) => decode_cp437(bytes),
+            },
+            FileNameEncoding::Cp437 => decode_cp437(bytes),
+            FileNameEncoding::Custom(decode) => decode(bytes),
+        }
+    }
+    //返回解码后的名称，非 UTF-8 标记的非 ASCII 名称同时保留原始字节
+    pub fn decode_file_name(&self, flags: u16, bytes: Vec<u8>) -> (String, Option<RawFileName>) {
+        let file_name = self.decode(flags, &bytes);
+        if flags & UTF8_FLAG != 0 || bytes.is_ascii() {
+            return (file_name, None);
+        }
+        let raw_file_name = RawFileName {
+            decoded: file_name.clone(),
+            bytes,
+        };
+        (file_name, Some(raw_file_name))
+    }
+}
+
+//缓存中只保存编码的类型，Custom 的函数不能缓存
+impl ValueRead for FileNameEncoding {
+    fn read(stream: &mut Stream) -> Result<Self> {
+        let value: u8 = stream.read_value()?;
+        Ok(match value {
+            1 => FileNameEncoding::Cp437,
+            _ => FileNameEncoding::Auto,
+        })
+    }
+}
+impl ValueWrite for FileNameEncoding {
+    fn write(self, endian: &Endian) -> Result<Stream> {
+        let value: u8 = match self {
+            FileNameEncoding::Auto => 0,
+            FileNameEncoding::Cp437 => 1,
+            FileNameEncoding::Custom(_) => return Err(ZipError::CustomEncodingCache.into()),
+        };
+        let mut stream = Stream::empty();
+        stream.with_endian(endian.clone());
+        stream.write_value(value)?;
+        Ok(stream)
+    }
+}
+
+pub fn decode_cp437(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|byte| {
+            if *byte < 0x80 {
+                *byte as char
+            } else {
+                CP437_HIGH[(*byte - 0x80) as usize]
+            }
+        })
+        .collect()
+}
+
+//原始的文件名字节，名称未修改时原样写回
+#[derive(Debug, Clone)]
+pub struct RawFileName {
+    pub decoded: String,
+    pub bytes: Vec<u8>,
+}
+impl RawFileName {
+    //有效的 Unicode Path 优先，否则按 flags 和指定的编码解码
+    pub fn decode(
+        extra_fields: &[Extra],
+        flags: u16,
+        bytes: Vec<u8>,
+        encoding: &FileNameEncoding,
+    ) -> (String, Option<Self>) {
+        match Extra::find_unicode_path(extra_fields, &bytes) {
+            Some(unicode_path) => {
+                let raw_file_name = RawFileName {
+                    decoded: unicode_path.clone(),
+                    bytes,
+                };
+                (unicode_path, Some(raw_file_name))
+            }
+            None => encoding.decode_file_name(flags, bytes),
+        }
+    }
+    //返回写入的文件名字节和对应的 flags
+    pub fn encode(file_name: &str, raw_file_name: &Option<RawFileName>, flags: u16) -> (Vec<u8>, u16) {
+        if let Some(raw_file_name) = raw_file_name
+            && raw_file_name.decoded == file_name
+        {
+            return (raw_file_name.bytes.clone(), flags & !UTF8_FLAG);
+        }
+        if file_name.is_ascii() {
+            (file_name.as_bytes().to_vec(), flags)
+        } else {
+            (file_name.as_bytes().to_vec(), flags | UTF8_FLAG)
+        }
+    }
+}
+impl ValueRead for RawFileName {
+    fn read(stream: &mut Stream) -> Result<Self> {
+        Ok(Self {
+            decoded: stream.read_value()?,
+            bytes: stream.read_value()?,
+        })
+    }
+}
+impl ValueWrite for RawFileName {
+    fn write(self, endian: &Endian) -> Result<Stream> {
+        let mut stream = Stream::empty();
+        stream.with_endian(endian.clone());
+        stream.write_value(self.decoded)?;
+        stream.write_value(self.bytes)?;
+        Ok(stream)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::zip::Zip;
+
+    //Python 生成，文件名是 CP437 的 "Çü.txt"，没有 bit 11
+    const CP437_ARCHIVE: [u8; 116] = [
+        0x50, 0x4b, 0x03, 0x04, 0x14, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x60, 0x21, 0x5a, 0x8d,
+        0x41, 0xc6, 0xef, 0x06, 0x00, 0x00, 0x00, 0x06, 0x00, 0x00, 0x00, 0x06, 0x00, 0x00, 0x00,
+        0x80, 0x81, 0x2e, 0x74, 0x78, 0x74, 0x63, 0x70, 0x34, 0x33, 0x37, 0x0a, 0x50, 0x4b, 0x01,
+        0x02, 0x14, 0x00, 0x14, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x60, 0x21, 0x5a, 0x8d, 0x41,
+        0xc6, 0xef, 0x06, 0x00, 0x00, 0x00, 0x06, 0x00, 0x00, 0x00, 0x06, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x80, 0x81,
+        0x2e, 0x74, 0x78, 0x74, 0x50, 0x4b, 0x05, 0x06, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x01,
+        0x00, 0x34, 0x00, 0x00, 0x00, 0x2a, 0x00, 0x00, 0x00, 0x00, 0x00,
+    ];
+
+    #[test]
+    fn decode_file_names() {
+        assert_eq!(decode_cp437(&[0x80, 0x81, 0xE1, b'a']), "Çüßa");
+        let (auto, cp437) = (FileNameEncoding::Auto, FileNameEncoding::Cp437);
+        assert_eq!(auto.decode(0, &[0x80, 0x81]), "Çü");
+        assert_eq!(auto.decode(0, "é".as_bytes()), "é");
+        assert_eq!(auto.decode(UTF8_FLAG, "é".as_bytes()), "é");
+        assert_eq!(cp437.decode(0, "é".as_bytes()), "├⌐");
+        assert_eq!(cp437.decode(UTF8_FLAG, "é".as_bytes()), "é");
+    }
+    #[test]
+    fn keep_raw_file_name() {
+        let (file_name, raw_file_name) = FileNameEncoding::Auto.decode_file_name(0, vec![0x80]);
+        assert_eq!(file_name, "Ç");
+        assert_eq!(
+            RawFileName::encode(&file_name, &raw_file_name, UTF8_FLAG),
+            (vec![0x80], 0)
+        );
+        //改名后按 UTF-8 写入
+        assert_eq!(
+            RawFileName::encode("é", &raw_file_name, 0),
+            ("é".as_bytes().to_vec(), UTF8_FLAG)
+        );
+    }
+    #[test]
+    fn rewrite_cp437_archive() {
+        let mut zip = Zip::new(Stream::new(CP437_ARCHIVE.to_vec().into())).unwrap();
+        assert!(zip.directories.contains_key("Çü.txt"));
+        let mut output = Stream::empty();
+        zip.package(&mut output, &mut |_, _, _| {}).unwrap();
+        let data = output.take_data().unwrap();
+        assert_eq!(data[6..8], [0, 0]);
+        assert_eq!(data[30..36], *b"\x80\x81.txt");
+
+        let mut zip = Zip::new(Stream::new(data.into())).unwrap();
+        let directory = zip.directory("Çü.txt").unwrap();
+        assert_eq!(directory.flags & UTF8_FLAG, 0);
+        let data = directory.decompressed().unwrap().take_data().unwrap();
+        assert_eq!(data, b"cp437\n");
+    }
+}
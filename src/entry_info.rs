@@ -0,0 +1,287 @@
+use crate::crypto::ENCRYPTED_FLAG;
+use crate::directory::CompressionMethod;
+use crate::encoding::{FileNameEncoding, RawFileName};
+use crate::eocd::EoCd;
+use crate::error::ZipError;
+use crate::extra::Extra;
+use crate::magic::Magic;
+use crate::zip::Parser;
+use crate::zip_file::{DataDescriptor, ZipFile};
+use fast_stream::bytes::{Bytes, ValueRead};
+use fast_stream::pin::Pin;
+use fast_stream::stream::Stream;
+use std::io::{Result, Seek, SeekFrom};
+use std::time::SystemTime;
+
+//中央目录中的条目信息，不读取本地头和数据
+#[derive(Debug, Clone)]
+pub struct EntryInfo {
+    pub created_zip_spec: u8,
+    pub created_os: u8,
+    pub extract_zip_spec: u8,
+    pub extract_os: u8,
+    pub flags: u16,
+    pub compression_method: CompressionMethod,
+    pub last_modification_time: u16,
+    pub last_modification_date: u16,
+    pub crc_32_uncompressed_data: u32,
+    pub compressed_size: u64,
+    pub uncompressed_size: u64,
+    pub file_name_length: u16,
+    pub extra_field_length: u16,
+    pub file_comment_length: u16,
+    pub number_of_starts: u16,
+    pub internal_file_attributes: u16,
+    pub external_file_attributes: u32,
+    pub offset_of_local_file_header: u64,
+    pub file_name: String,
+    pub raw_file_name: Option<RawFileName>,
+    pub extra_fields: Vec<Extra>,
+    pub file_comment: Vec<u8>,
+}
+impl EntryInfo {
+    pub fn is_dir(&self) -> bool {
+        self.file_name.ends_with("/")
+    }
+    pub fn is_encrypted(&self) -> bool {
+        self.flags & ENCRYPTED_FLAG != 0
+    }
+    pub fn modified(&self) -> SystemTime {
+        Extra::find_modified(
+            &self.extra_fields,
+            self.last_modification_time,
+            self.last_modification_date,
+        )
+    }
+    //读取本地头并与中央目录比对
+    pub fn local_file(
+        &self,
+        stream: &mut Stream,
+        encoding: &FileNameEncoding,
+    ) -> Result<ZipFile<Parser>> {
+        stream.pin()?;
+        stream.seek(SeekFrom::Start(self.offset_of_local_file_header))?;
+        let file = ZipFile::read_with(stream, encoding)
+            .map_err(|e| ZipError::map_eof(e, self.offset_of_local_file_header));
+        stream.un_pin()?;
+        let file = file?;
+        let (file_name_bytes, _) =
+            RawFileName::encode(&self.file_name, &self.raw_file_name, self.flags);
+        file.check_file_name(&self.file_name, &file_name_bytes)?;
+        file.check_central(
+            &self.file_name,
+            &self.compression_method,
+            self.flags,
+            self.crc_32_uncompressed_data,
+            self.compressed_size,
+            self.uncompressed_size,
+        )?;
+        Ok(file)
+    }
+    //按中央目录的字段构造本地头，数据位置在首次读取数据时才从本地头解析
+    pub fn to_local_file(&self) -> ZipFile<Parser> {
+        let data_descriptor = (self.flags & 0x0008 != 0).then_some(DataDescriptor {
+            signature: true,
+            crc32: self.crc_32_uncompressed_data,
+            compressed_size: self.compressed_size,
+            uncompressed_size: self.uncompressed_size,
+            zip64: false,
+        });
+        ZipFile {
+            r#type: Parser,
+            extract_zip_spec: self.extract_zip_spec,
+            extract_os: self.extract_os,
+            flags: self.flags,
+            compression_method: self.compression_method.clone(),
+            last_modification_time: self.last_modification_time,
+            last_modification_date: self.last_modification_date,
+            crc_32_uncompressed_data: self.crc_32_uncompressed_data,
+            compressed_size: self.compressed_size,
+            uncompressed_size: self.uncompressed_size,
+            file_name_length: self.file_name_length,
+            extra_field_length: self.extra_field_length,
+            file_name: self.file_name.clone(),
+            raw_file_name: self.raw_file_name.clone(),
+            extra_fields: self.extra_fields.clone(),
+            data_descriptor,
+            data_position: 0,
+        }
+    }
+}
+impl ValueRead for EntryInfo {
+    fn read(stream: &mut Stream) -> Result<Self> {
+        Self::read_with(stream, &FileNameEncoding::default())
+    }
+}
+impl EntryInfo {
+    //文件名按指定的编码解码
+    pub fn read_with(stream: &mut Stream, encoding: &FileNameEncoding) -> Result<Self> {
+        Magic::Directory.expect(stream)?;
+        let created_zip_spec: u8 = stream.read_value()?;
+        let created_os: u8 = stream.read_value()?;
+        let extract_zip_spec: u8 = stream.read_value()?;
+        let extract_os: u8 = stream.read_value()?;
+        let flags: u16 = stream.read_value()?;
+        let compression_method: CompressionMethod = stream.read_value()?;
+        let last_modification_time: u16 = stream.read_value()?;
+        let last_modification_date: u16 = stream.read_value()?;
+        let crc_32_uncompressed_data: u32 = stream.read_value()?;
+        let compressed_size: u32 = stream.read_value()?;
+        let uncompressed_size: u32 = stream.read_value()?;
+        let file_name_length: u16 = stream.read_value()?;
+        let extra_field_length: u16 = stream.read_value()?;
+        let file_comment_length: u16 = stream.read_value()?;
+        let number_of_starts: u16 = stream.read_value()?;
+        let internal_file_attributes: u16 = stream.read_value()?;
+        let external_file_attributes: u32 = stream.read_value()?;
+        let offset_of_local_file_header: u32 = stream.read_value()?;
+        let file_name = stream.read_exact_size(file_name_length as u64)?;
+        let mut extra_fields = Extra::read_fields(stream, extra_field_length)?;
+        let file_comment = stream.read_exact_size(file_comment_length as u64)?;
+        let (file_name, raw_file_name) =
+            RawFileName::decode(&extra_fields, flags, file_name, encoding);
+        let mut compressed_size = compressed_size as u64;
+        let mut uncompressed_size = uncompressed_size as u64;
+        let mut offset_of_local_file_header = offset_of_local_file_header as u64;
+        for extra_field in &mut extra_fields {
+            if let Extra::Zip64 { .. } = extra_field {
+                extra_field.resolve_zip64(
+                    uncompressed_size == u32::MAX as u64,
+                    compressed_size == u32::MAX as u64,
+                    offset_of_local_file_header == u32::MAX as u64,
+                );
+            }
+            if let Extra::Zip64 {
+                uncompressed_size: zip64_uncompressed_size,
+                compressed_size: zip64_compressed_size,
+                offset,
+                ..
+            } = extra_field
+            {
+                uncompressed_size = zip64_uncompressed_size.unwrap_or(uncompressed_size);
+                compressed_size = zip64_compressed_size.unwrap_or(compressed_size);
+                offset_of_local_file_header = offset.unwrap_or(offset_of_local_file_header);
+            }
+        }
+        Ok(Self {
+            created_zip_spec,
+            created_os,
+            extract_zip_spec,
+            extract_os,
+            flags,
+            compression_method,
+            last_modification_time,
+            last_modification_date,
+            crc_32_uncompressed_data,
+            compressed_size,
+            uncompressed_size,
+            file_name_length,
+            extra_field_length,
+            file_comment_length,
+            number_of_starts,
+            internal_file_attributes,
+            external_file_attributes,
+            offset_of_local_file_header,
+            file_name,
+            raw_file_name,
+            extra_fields,
+            file_comment,
+        })
+    }
+}
+
+pub struct Entries<'a> {
+    stream: &'a mut Stream,
+    remaining: u64,
+    validate: bool,
+    encoding: FileNameEncoding,
+}
+impl<'a> Entries<'a> {
+    //文件名按 encoding 解码，与 Zip::with_file_name_encoding 一致
+    pub fn new(stream: &'a mut Stream, encoding: FileNameEncoding) -> Result<Self> {
+        let eo_cd = stream.read_value::<EoCd<Parser>>()?;
+        stream.set_position(eo_cd.offset)?;
+        Ok(Self {
+            stream,
+            remaining: eo_cd.entries,
+            validate: false,
+            encoding,
+        })
+    }
+    //迭代时同时读取并校验本地头
+    pub fn with_validate(mut self, value: bool) -> Self {
+        self.validate = value;
+        self
+    }
+    pub fn remaining(&self) -> u64 {
+        self.remaining
+    }
+    fn read_next(&mut self) -> Result<EntryInfo> {
+        let offset = self.stream.stream_position()?;
+        let info = EntryInfo::read_with(self.stream, &self.encoding)
+            .map_err(|e| ZipError::map_eof(e, offset))?;
+        if self.validate {
+            info.local_file(self.stream, &self.encoding)?;
+        }
+        Ok(info)
+    }
+}
+impl Iterator for Entries<'_> {
+    type Item = std::result::Result<EntryInfo, ZipError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        let info = self.read_next();
+        if info.is_err() {
+            self.remaining = 0;
+        }
+        Some(info.map_err(ZipError::from))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoding::UTF8_FLAG;
+    use crate::zip::Zip;
+
+    //UTF-8 的文件名去掉 bit 11，Auto 按 UTF-8 解码，Cp437 按 CP437 解码
+    fn legacy_archive() -> Vec<u8> {
+        let mut zip = Zip::create(Stream::empty());
+        zip.add_file(Stream::new(b"a".to_vec().into()), "é.txt")
+            .unwrap();
+        let mut output = Stream::empty();
+        zip.package(&mut output, &mut |_, _, _| {}).unwrap();
+        let mut data = output.take_data().unwrap();
+        let eocd = data.len() - 22;
+        let central = u32::from_le_bytes(data[eocd + 16..eocd + 20].try_into().unwrap()) as usize;
+        for flags in [6, central + 8] {
+            let value = u16::from_le_bytes([data[flags], data[flags + 1]]) & !UTF8_FLAG;
+            data[flags..flags + 2].copy_from_slice(&value.to_le_bytes());
+        }
+        data
+    }
+    fn names(data: &[u8], encoding: FileNameEncoding) -> Vec<String> {
+        let mut stream = Stream::new(data.to_vec().into());
+        let entries = Zip::entries(&mut stream, encoding).unwrap();
+        entries
+            .with_validate(true)
+            .map(|info| info.unwrap().file_name)
+            .collect()
+    }
+
+    #[test]
+    fn entries_use_file_name_encoding() {
+        let data = legacy_archive();
+        assert_eq!(names(&data, FileNameEncoding::Auto), ["é.txt"]);
+        assert_eq!(names(&data, FileNameEncoding::Cp437), ["├⌐.txt"]);
+
+        let mut zip = Zip::create(Stream::new(data.into()));
+        zip.with_file_name_encoding(FileNameEncoding::Cp437);
+        zip.parse().unwrap();
+        assert!(zip.directories.contains_key("├⌐.txt"));
+    }
+}
@@ -1,35 +1,57 @@
+use crate::directory::u16_length;
+use crate::error::ZipError;
 use crate::magic::Magic;
 use crate::zip::Parser;
 use fast_stream::bytes::{Bytes, ValueRead, ValueWrite};
 use fast_stream::endian::Endian;
 use fast_stream::pin::Pin;
 use fast_stream::stream::Stream;
-use std::io::{Result, Seek, SeekFrom};
+use std::io::{Result, Seek, SeekFrom, Write};
 
 #[derive(Debug, Clone)]
 pub struct EoCd<TYPE> {
     pub r#type: TYPE,
-    pub number_of_disk: u16,
-    pub directory_starts: u16,
-    pub number_of_directory_disk: u16,
-    pub entries: u16,
-    pub size: u32,
-    pub offset: u32,
-    pub comment_length: u16,
+    pub number_of_disk: u32,
+    pub directory_starts: u32,
+    pub number_of_directory_disk: u64,
+    pub entries: u64,
+    pub size: u64,
+    pub offset: u64,
+    pub comment: Vec<u8>,
+    pub zip64: bool,
 }
 
 impl ValueWrite for EoCd<Parser> {
     fn write(self, endian: &Endian) -> Result<Stream> {
         let mut output = Stream::empty();
         output.with_endian(endian.clone());
+        if self.zip64 {
+            //ZIP64 EOCD 紧跟在中央目录之后
+            output.write_value(Magic::Zip64EoCd)?;
+            output.write_value(Self::ZIP64_RECORD_SIZE)?;
+            output.write_value(ZIP64_ZIP_SPEC)?; //created_zip_spec
+            output.write_value(ZIP64_ZIP_SPEC)?; //extract_zip_spec
+            output.write_value(self.number_of_disk)?;
+            output.write_value(self.directory_starts)?;
+            output.write_value(self.number_of_directory_disk)?;
+            output.write_value(self.entries)?;
+            output.write_value(self.size)?;
+            output.write_value(self.offset)?;
+
+            output.write_value(Magic::Zip64EoCdLocator)?;
+            output.write_value(self.number_of_disk)?;
+            output.write_value(self.offset + self.size)?;
+            output.write_value(self.number_of_disk + 1)?; //total disks
+        }
         output.write_value(Magic::EoCd)?;
-        output.write_value(self.number_of_disk)?;
-        output.write_value(self.directory_starts)?;
-        output.write_value(self.number_of_directory_disk)?;
-        output.write_value(self.entries)?;
-        output.write_value(self.size)?;
-        output.write_value(self.offset)?;
-        output.write_value(self.comment_length)?;
+        output.write_value(self.number_of_disk.min(u16::MAX as u32) as u16)?;
+        output.write_value(self.directory_starts.min(u16::MAX as u32) as u16)?;
+        output.write_value(self.number_of_directory_disk.min(u16::MAX as u64) as u16)?;
+        output.write_value(self.entries.min(u16::MAX as u64) as u16)?;
+        output.write_value(self.size.min(u32::MAX as u64) as u32)?;
+        output.write_value(self.offset.min(u32::MAX as u64) as u32)?;
+        output.write_value(u16_length("", "archive comment", self.comment.len())?)?;
+        output.write_all(&self.comment)?;
         Ok(output)
     }
 }
@@ -40,10 +62,7 @@ impl EoCd<Parser> {
         let file_size = stream.length();
 
         if file_size < search_size {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::InvalidData,
-                "not a zip file",
-            ));
+            return Err(ZipError::EndOfCentralDirectoryNotFound.into());
         }
         let eocd_magic: u32 = Magic::EoCd.into();
         loop {
@@ -68,28 +87,122 @@ impl EoCd<Parser> {
             search_size = (search_size * 2).min(file_size);
         }
 
-        Err(std::io::Error::new(
-            std::io::ErrorKind::InvalidData,
-            "not a zip file",
-        ))
+        Err(ZipError::EndOfCentralDirectoryNotFound.into())
+    }
+}
+
+pub const ZIP64_ZIP_SPEC: u16 = 45; //4.5
+impl EoCd<Parser> {
+    pub const ZIP64_LOCATOR_SIZE: u64 = 20;
+    pub const ZIP64_RECORD_SIZE: u64 = 44;
+    pub fn need_zip64(&self) -> bool {
+        self.entries >= u16::MAX as u64
+            || self.number_of_directory_disk >= u16::MAX as u64
+            || self.size >= u32::MAX as u64
+            || self.offset >= u32::MAX as u64
+    }
+    //读取 ZIP64 EOCD 记录覆盖 16/32 位字段，eocd_position 为普通 EOCD 的绝对位置
+    pub fn read_zip64(&mut self, stream: &mut Stream, eocd_position: u64) -> Result<bool> {
+        if eocd_position < Self::ZIP64_LOCATOR_SIZE {
+            return Ok(false);
+        }
+        stream.seek(SeekFrom::Start(eocd_position - Self::ZIP64_LOCATOR_SIZE))?;
+        let locator_magic: u32 = Magic::Zip64EoCdLocator.into();
+        let magic: u32 = stream.read_value()?;
+        if magic != locator_magic {
+            return Ok(false);
+        }
+        let _zip64_eocd_disk: u32 = stream.read_value()?;
+        let zip64_eocd_offset: u64 = stream.read_value()?;
+        let _total_disks: u32 = stream.read_value()?;
+
+        stream.seek(SeekFrom::Start(zip64_eocd_offset))?;
+        Magic::Zip64EoCd.expect(stream)?;
+        let _record_size: u64 = stream.read_value()?;
+        let _created_zip_spec: u16 = stream.read_value()?;
+        let _extract_zip_spec: u16 = stream.read_value()?;
+        self.number_of_disk = stream.read_value()?;
+        self.directory_starts = stream.read_value()?;
+        self.number_of_directory_disk = stream.read_value()?;
+        self.entries = stream.read_value()?;
+        self.size = stream.read_value()?;
+        self.offset = stream.read_value()?;
+        self.zip64 = true;
+        Ok(true)
     }
 }
 
 impl ValueRead for EoCd<Parser> {
     fn read(stream: &mut Stream) -> Result<Self> {
         let eocd_offset = Self::find_offset(stream)?;
-        stream.seek(SeekFrom::End(-(eocd_offset as i64)))?;
+        let eocd_position = stream.seek(SeekFrom::End(-(eocd_offset as i64)))?;
         stream.seek(SeekFrom::Current(4))?;
 
-        Ok(EoCd {
+        let number_of_disk: u16 = stream.read_value()?;
+        let directory_starts: u16 = stream.read_value()?;
+        let number_of_directory_disk: u16 = stream.read_value()?;
+        let entries: u16 = stream.read_value()?;
+        let size: u32 = stream.read_value()?;
+        let offset: u32 = stream.read_value()?;
+        let comment_length: u16 = stream.read_value()?;
+        let comment = stream
+            .read_exact_size(comment_length as u64)
+            .map_err(|e| ZipError::map_eof(e, eocd_position))?;
+        let mut eo_cd = EoCd {
             r#type: Parser,
-            number_of_disk: stream.read_value()?,
-            directory_starts: stream.read_value()?,
-            number_of_directory_disk: stream.read_value()?,
-            entries: stream.read_value()?,
-            size: stream.read_value()?,
-            offset: stream.read_value()?,
-            comment_length: stream.read_value()?,
-        })
+            number_of_disk: number_of_disk as u32,
+            directory_starts: directory_starts as u32,
+            number_of_directory_disk: number_of_directory_disk as u64,
+            entries: entries as u64,
+            size: size as u64,
+            offset: offset as u64,
+            comment,
+            zip64: false,
+        };
+        //有 locator 就以 ZIP64 记录为准
+        eo_cd.read_zip64(stream, eocd_position)?;
+        Ok(eo_cd)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::zip::Zip;
+    use fast_stream::stream::Stream;
+
+    //Python 按规范生成：本地头和中央目录的大小、偏移都是 0xFFFFFFFF，EOCD 的条目数是 0xFFFF
+    const ZIP64_ARCHIVE: [u8; 244] = [
+        0x50, 0x4b, 0x03, 0x04, 0x2d, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x60, 0x21, 0x5a, 0x0d,
+        0xa8, 0x52, 0x28, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x05, 0x00, 0x14, 0x00,
+        0x61, 0x2e, 0x74, 0x78, 0x74, 0x01, 0x00, 0x10, 0x00, 0x0c, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x0c, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x7a, 0x69, 0x70, 0x36, 0x34,
+        0x20, 0x65, 0x6e, 0x74, 0x72, 0x79, 0x0a, 0x50, 0x4b, 0x01, 0x02, 0x2d, 0x00, 0x2d, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x60, 0x21, 0x5a, 0x0d, 0xa8, 0x52, 0x28, 0xff, 0xff, 0xff,
+        0xff, 0xff, 0xff, 0xff, 0xff, 0x05, 0x00, 0x1c, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0xff, 0xff, 0xff, 0xff, 0x61, 0x2e, 0x74, 0x78, 0x74, 0x01, 0x00,
+        0x18, 0x00, 0x0c, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x0c, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x50, 0x4b, 0x06, 0x06,
+        0x2c, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x2d, 0x00, 0x2d, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x4f, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x43,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x50, 0x4b, 0x06, 0x07, 0x00, 0x00, 0x00, 0x00,
+        0x92, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x50, 0x4b, 0x05,
+        0x06, 0x00, 0x00, 0x00, 0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        0xff, 0xff, 0x00, 0x00,
+    ];
+
+    #[test]
+    fn read_zip64() {
+        let mut zip = Zip::new(Stream::new(ZIP64_ARCHIVE.to_vec().into())).unwrap();
+        let eo_cd = zip.eo_cd.as_ref().unwrap();
+        assert!(eo_cd.zip64);
+        assert_eq!(eo_cd.entries, 1);
+        assert_eq!(eo_cd.offset, 67);
+        let directory = zip.directory("a.txt").unwrap();
+        assert_eq!(directory.compressed_size, 12);
+        assert_eq!(directory.uncompressed_size, 12);
+        assert_eq!(directory.offset_of_local_file_header, 0);
+        let data = directory.decompressed().unwrap().take_data().unwrap();
+        assert_eq!(data, b"zip64 entry\n");
     }
 }
@@ -1,3 +1,4 @@
+use std::io::{Error, ErrorKind};
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -5,5 +6,120 @@ pub enum ZipError {
     #[error("{0}")]
     Error(String),
     #[error(transparent)]
-    IoError(#[from] std::io::Error),
+    IoError(Error),
+    #[error("Not a zip file: end of central directory not found")]
+    EndOfCentralDirectoryNotFound,
+    #[error("Bad signature at offset {offset}: expected {expected:08x}, found {found:08x}")]
+    BadSignature {
+        expected: u32,
+        found: u32,
+        offset: u64,
+    },
+    #[error(
+        "Truncated archive at offset {offset}{}",
+        .name.as_ref().map(|name| format!(": {name}")).unwrap_or_default()
+    )]
+    Truncated { name: Option<String>, offset: u64 },
+    #[error("Invalid extra field {header_id:#06x} at offset {offset}")]
+    InvalidExtraField { header_id: u16, offset: u64 },
+    #[error("Local header {field} not match central directory: {name}")]
+    LocalHeaderMismatch { name: String, field: &'static str },
+    #[error("Entry not found: {name}")]
+    EntryNotFound { name: String },
+    #[error("Unsafe path: {name}")]
+    UnsafePath { name: String },
+    #[error("{field} length {value} exceeds {limit}: {name}")]
+    LimitExceeded {
+        name: String,
+        field: &'static str,
+        value: u64,
+        limit: u64,
+    },
+    #[error("Compression level {level} out of range {min}..={max}: {name}")]
+    InvalidLevel {
+        name: String,
+        level: i32,
+        min: i32,
+        max: i32,
+    },
+    #[error("Unsupported feature {feature}: {name}")]
+    UnsupportedFeature { name: String, feature: &'static str },
+    #[error("Unsupported compression method {method}: {name}")]
+    UnsupportedMethod { name: String, method: u16 },
+    #[error("Entry is encrypted, password required: {name}")]
+    Encrypted { name: String },
+    #[error("Password required for AES encryption: {name}")]
+    PasswordRequired { name: String },
+    #[error("Invalid password: {name}")]
+    InvalidPassword { name: String },
+    #[error("Authentication code mismatch: {name}")]
+    AuthenticationFailed { name: String },
+    #[error("CRC-32 mismatch {name}: expected {expected:08x}, found {actual:08x}")]
+    ChecksumMismatch {
+        name: String,
+        expected: u32,
+        actual: u32,
+    },
+    #[error("Size mismatch {name}: expected {expected}, found {actual}")]
+    SizeMismatch {
+        name: String,
+        expected: u64,
+        actual: u64,
+    },
+    #[error("Custom file name encoding cannot be cached")]
+    CustomEncodingCache,
+    #[error("Source stream lock poisoned")]
+    SourcePoisoned,
+}
+impl ZipError {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            ZipError::IoError(error) => error.kind(),
+            ZipError::Truncated { .. } => ErrorKind::UnexpectedEof,
+            ZipError::SourcePoisoned => ErrorKind::Other,
+            ZipError::EntryNotFound { .. } => ErrorKind::NotFound,
+            ZipError::UnsupportedFeature { .. } | ZipError::UnsupportedMethod { .. } => {
+                ErrorKind::Unsupported
+            }
+            ZipError::UnsafePath { .. }
+            | ZipError::LimitExceeded { .. }
+            | ZipError::InvalidLevel { .. }
+            | ZipError::Encrypted { .. }
+            | ZipError::PasswordRequired { .. }
+            | ZipError::CustomEncodingCache
+            | ZipError::InvalidPassword { .. } => ErrorKind::InvalidInput,
+            _ => ErrorKind::InvalidData,
+        }
+    }
+    //读到结尾说明数据被截断，offset 为正在读取的记录的位置
+    pub(crate) fn map_eof(error: Error, offset: u64) -> Error {
+        if error.kind() == ErrorKind::UnexpectedEof && !Self::is_wrapped(&error) {
+            ZipError::Truncated { name: None, offset }.into()
+        } else {
+            error
+        }
+    }
+    fn is_wrapped(error: &Error) -> bool {
+        error.get_ref().is_some_and(|inner| inner.is::<ZipError>())
+    }
+}
+//ValueRead 只能返回 io::Error，结构化的错误包在其中传出，转换时再取出来
+impl From<Error> for ZipError {
+    fn from(error: Error) -> Self {
+        if !Self::is_wrapped(&error) {
+            return ZipError::IoError(error);
+        }
+        match error.into_inner().map(|inner| inner.downcast::<ZipError>()) {
+            Some(Ok(inner)) => *inner,
+            _ => unreachable!("checked by is_wrapped"),
+        }
+    }
+}
+impl From<ZipError> for Error {
+    fn from(error: ZipError) -> Self {
+        match error {
+            ZipError::IoError(error) => error,
+            error => Error::new(error.kind(), error),
+        }
+    }
 }
@@ -1,7 +1,13 @@
+use crate::crc32::crc32;
+use crate::crypto::{AES_EXTRA_DATA_SIZE, AES_VENDOR_ID, AesStrength};
+use crate::datetime;
+use crate::directory::CompressionMethod;
+use crate::encoding::UTF8_FLAG;
 use fast_stream::bytes::{Bytes, ValueRead, ValueWrite};
 use fast_stream::endian::Endian;
 use fast_stream::stream::Stream;
-use std::io::{Error, ErrorKind, Result};
+use std::io::{Result, Write};
+use std::time::SystemTime;
 
 //https://libzip.org/specifications/extrafld.txt
 #[derive(Debug, Clone)]
@@ -20,6 +26,36 @@ pub enum Extra {
         uid: u32,
         gid: u32,
     },
+    Zip64 {
+        uncompressed_size: Option<u64>,
+        compressed_size: Option<u64>,
+        offset: Option<u64>,
+        disk: Option<u32>,
+    },
+    UnicodePath {
+        version: u8,
+        crc32: u32,
+        name: String,
+    },
+    UnicodeComment {
+        version: u8,
+        crc32: u32,
+        comment: String,
+    },
+    //WinZip AES，compression_method 为加密前的实际压缩方式
+    Aes {
+        version: u16,
+        strength: AesStrength,
+        compression_method: CompressionMethod,
+    },
+    Unknown {
+        id: u16,
+        data: Vec<u8>,
+    },
+    //扩展字段区域末尾放不下一个字段头的字节，写回时不带头部
+    Padding {
+        data: Vec<u8>,
+    },
 }
 impl Extra {
     pub fn optional_field_size<T: Sized>(field: &Option<T>) -> u16 {
@@ -29,7 +65,10 @@ impl Extra {
         }
     }
     pub fn size(&self) -> u16 {
-        2 + 2 + self.field_size()
+        match self {
+            Extra::Padding { data } => data.len() as u16,
+            _ => 2 + 2 + self.field_size(),
+        }
     }
     pub fn field_size(&self) -> u16 {
         match self {
@@ -45,6 +84,21 @@ impl Extra {
                     + Self::optional_field_size(ctime)
             }
             Extra::UnixAttrs { .. } => 11,
+            Extra::Zip64 {
+                uncompressed_size,
+                compressed_size,
+                offset,
+                disk,
+            } => {
+                Self::optional_field_size(uncompressed_size)
+                    + Self::optional_field_size(compressed_size)
+                    + Self::optional_field_size(offset)
+                    + Self::optional_field_size(disk)
+            }
+            Extra::UnicodePath { name, .. } => 1 + 4 + name.len() as u16,
+            Extra::UnicodeComment { comment, .. } => 1 + 4 + comment.len() as u16,
+            Extra::Aes { .. } => AES_EXTRA_DATA_SIZE,
+            Extra::Unknown { data, .. } | Extra::Padding { data } => data.len() as u16,
         }
     }
     pub fn header_id(&self) -> u16 {
@@ -52,6 +106,12 @@ impl Extra {
             Extra::NTFS { .. } => 0x000a,
             Extra::UnixExtendedTimestamp { .. } => 0x5455,
             Extra::UnixAttrs { .. } => 0x7875,
+            Extra::Zip64 { .. } => 0x0001,
+            Extra::UnicodePath { .. } => 0x7075,
+            Extra::UnicodeComment { .. } => 0x6375,
+            Extra::Aes { .. } => 0x9901,
+            Extra::Unknown { id, .. } => *id,
+            Extra::Padding { .. } => 0,
         }
     }
     pub fn if_present(val: Option<i32>, if_present: u8) -> u8 {
@@ -60,11 +120,160 @@ impl Extra {
             None => 0,
         }
     }
+    //头部中为旧编码的文件名时，附带 UTF-8 的文件名
+    pub fn unicode_path(name: &str, header_file_name: &[u8]) -> Self {
+        Extra::UnicodePath {
+            version: 1,
+            crc32: crc32(header_file_name),
+            name: name.to_string(),
+        }
+    }
+    pub fn unicode_comment(comment: &str, header_comment: &[u8]) -> Self {
+        Extra::UnicodeComment {
+            version: 1,
+            crc32: crc32(header_comment),
+            comment: comment.to_string(),
+        }
+    }
+    //头部文件名为旧编码时保证有一个有效的 Unicode Path，去掉 crc 不一致的
+    pub fn update_unicode_path(
+        extra_fields: &mut Vec<Extra>,
+        name: &str,
+        header_file_name: &[u8],
+        flags: u16,
+    ) {
+        let legacy = flags & UTF8_FLAG == 0 && !header_file_name.is_ascii();
+        let crc = crc32(header_file_name);
+        extra_fields.retain(|extra_field| match extra_field {
+            Extra::UnicodePath {
+                crc32, name: path, ..
+            } => legacy && *crc32 == crc && path == name,
+            _ => true,
+        });
+        if legacy && Self::find_unicode_path(extra_fields, header_file_name).is_none() {
+            extra_fields.push(Self::unicode_path(name, header_file_name));
+        }
+    }
+    pub fn update_unicode_comment(
+        extra_fields: &mut Vec<Extra>,
+        comment: &str,
+        header_comment: &[u8],
+        flags: u16,
+    ) {
+        let legacy = flags & UTF8_FLAG == 0 && !header_comment.is_ascii();
+        let crc = crc32(header_comment);
+        extra_fields.retain(|extra_field| match extra_field {
+            Extra::UnicodeComment {
+                crc32, comment: text, ..
+            } => legacy && *crc32 == crc && text == comment,
+            _ => true,
+        });
+        if legacy && Self::find_unicode_comment(extra_fields, header_comment).is_none() {
+            extra_fields.push(Self::unicode_comment(comment, header_comment));
+        }
+    }
+    //crc 与头部的文件名一致时 Unicode Path 才有效
+    pub fn find_unicode_path(extra_fields: &[Extra], header_file_name: &[u8]) -> Option<String> {
+        extra_fields.iter().find_map(|extra_field| match extra_field {
+            Extra::UnicodePath { crc32: crc, name, .. } if *crc == crc32(header_file_name) => {
+                Some(name.clone())
+            }
+            _ => None,
+        })
+    }
+    pub fn find_unicode_comment(extra_fields: &[Extra], header_comment: &[u8]) -> Option<String> {
+        extra_fields.iter().find_map(|extra_field| match extra_field {
+            Extra::UnicodeComment {
+                crc32: crc,
+                comment,
+                ..
+            } if *crc == crc32(header_comment) => Some(comment.clone()),
+            _ => None,
+        })
+    }
+    //精度 NTFS(100ns) > 扩展时间戳(1s) > DOS(2s)
+    pub fn find_modified(extra_fields: &[Extra], time: u16, date: u16) -> SystemTime {
+        let mut unix_mtime = None;
+        for extra_field in extra_fields {
+            match extra_field {
+                Extra::NTFS { mtime, .. } if *mtime != 0 => {
+                    let (seconds, nanos) = datetime::from_ntfs(*mtime);
+                    return datetime::system_time(seconds, nanos);
+                }
+                Extra::UnixExtendedTimestamp {
+                    mtime: Some(mtime), ..
+                } => unix_mtime = Some(*mtime),
+                _ => {}
+            }
+        }
+        let seconds = match unix_mtime {
+            Some(mtime) => mtime as i64,
+            None => datetime::from_dos(time, date),
+        };
+        datetime::system_time(seconds, 0)
+    }
+    //返回 (version, strength, compression_method)
+    pub fn find_aes(extra_fields: &[Extra]) -> Option<(u16, AesStrength, CompressionMethod)> {
+        extra_fields.iter().find_map(|extra_field| match extra_field {
+            Extra::Aes {
+                version,
+                strength,
+                compression_method,
+            } => Some((*version, *strength, compression_method.clone())),
+            _ => None,
+        })
+    }
+    //返回 (uncompressed_size, compressed_size, offset) 是否写在 ZIP64 字段中
+    pub fn zip64_fields(extra_fields: &[Extra]) -> (bool, bool, bool) {
+        for extra_field in extra_fields {
+            if let Extra::Zip64 {
+                uncompressed_size,
+                compressed_size,
+                offset,
+                ..
+            } = extra_field
+            {
+                return (
+                    uncompressed_size.is_some(),
+                    compressed_size.is_some(),
+                    offset.is_some(),
+                );
+            }
+        }
+        (false, false, false)
+    }
+    //ZIP64 字段只包含头部中为 0xFFFFFFFF 的值，读取时按顺序填充，这里按实际缺失的字段重新对应
+    pub fn resolve_zip64(&mut self, uncompressed: bool, compressed: bool, local_offset: bool) {
+        if let Extra::Zip64 {
+            uncompressed_size,
+            compressed_size,
+            offset,
+            ..
+        } = self
+        {
+            let mut values = [uncompressed_size.take(), compressed_size.take(), offset.take()]
+                .into_iter()
+                .flatten();
+            if uncompressed {
+                *uncompressed_size = values.next();
+            }
+            if compressed {
+                *compressed_size = values.next();
+            }
+            if local_offset {
+                *offset = values.next();
+            }
+        }
+    }
 }
 impl ValueWrite for Extra {
     fn write(self, endian: &Endian) -> Result<Stream> {
         let mut stream = Stream::empty();
         stream.with_endian(endian.clone());
+        if let Extra::Padding { data } = self {
+            stream.write_all(&data)?;
+            return Ok(stream);
+        }
         stream.write_value(self.header_id())?;
         let size = self.field_size();
         stream.write_value(size)?;
@@ -110,6 +319,57 @@ impl ValueWrite for Extra {
                 stream.write_value(4_u8)?;
                 stream.write_value(gid)?;
             }
+            Extra::Zip64 {
+                uncompressed_size,
+                compressed_size,
+                offset,
+                disk,
+            } => {
+                if let Some(uncompressed_size) = uncompressed_size {
+                    stream.write_value(uncompressed_size)?;
+                }
+                if let Some(compressed_size) = compressed_size {
+                    stream.write_value(compressed_size)?;
+                }
+                if let Some(offset) = offset {
+                    stream.write_value(offset)?;
+                }
+                if let Some(disk) = disk {
+                    stream.write_value(disk)?;
+                }
+            }
+            Extra::UnicodePath {
+                version,
+                crc32,
+                name,
+            } => {
+                stream.write_value(version)?;
+                stream.write_value(crc32)?;
+                stream.write_all(name.as_bytes())?;
+            }
+            Extra::UnicodeComment {
+                version,
+                crc32,
+                comment,
+            } => {
+                stream.write_value(version)?;
+                stream.write_value(crc32)?;
+                stream.write_all(comment.as_bytes())?;
+            }
+            Extra::Aes {
+                version,
+                strength,
+                compression_method,
+            } => {
+                stream.write_value(version)?;
+                stream.write_value(AES_VENDOR_ID)?;
+                stream.write_value(strength as u8)?;
+                stream.write_value(compression_method)?;
+            }
+            Extra::Unknown { data, .. } => {
+                stream.write_all(&data)?;
+            }
+            Extra::Padding { .. } => {}
         }
         Ok(stream)
     }
@@ -117,27 +377,77 @@ impl ValueWrite for Extra {
 impl ValueRead for Extra {
     fn read(stream: &mut Stream) -> Result<Self> {
         let id: u16 = stream.read_value()?;
-        Ok(match id {
-            0x5855 => {
-                let mut _length: u16 = stream.read_value()?;
-                let mtime = if _length > 0 {
-                    _length -= 4;
-                    Some(stream.read_value()?)
-                } else {
-                    None
-                };
-                let atime = if _length > 0 {
-                    _length -= 4;
-                    Some(stream.read_value()?)
-                } else {
-                    None
-                };
-                let ctime = if _length > 0 {
-                    _length -= 4;
+        let length: u16 = stream.read_value()?;
+        let data = stream.read_exact_size(length as u64)?;
+        Ok(Self::parse_field(id, data))
+    }
+}
+impl Extra {
+    //按头部声明的长度切出整个扩展字段区域后再逐个解析，
+    //不足 4 字节或超出区域的尾部(如旧版 zipalign 的填充)作为 Padding 原样保留
+    pub fn read_fields(stream: &mut Stream, length: u16) -> Result<Vec<Extra>> {
+        let data = stream.read_exact_size(length as u64)?;
+        Ok(Self::parse_fields(&data))
+    }
+    pub fn parse_fields(data: &[u8]) -> Vec<Extra> {
+        let mut extra_fields = vec![];
+        let mut rest = data;
+        while rest.len() >= 4 {
+            let id = u16::from_le_bytes([rest[0], rest[1]]);
+            let size = u16::from_le_bytes([rest[2], rest[3]]) as usize;
+            if rest.len() - 4 < size {
+                break;
+            }
+            extra_fields.push(Self::parse_field(id, rest[4..4 + size].to_vec()));
+            rest = &rest[4 + size..];
+        }
+        if !rest.is_empty() {
+            extra_fields.push(Extra::Padding {
+                data: rest.to_vec(),
+            });
+        }
+        extra_fields
+    }
+    //已知的字段内容不合法时作为 Unknown 原样保留，不影响整个归档的读取
+    fn parse_field(id: u16, data: Vec<u8>) -> Self {
+        let length = data.len() as u64;
+        let mut stream = Stream::new(data.clone().into());
+        match Self::read_field(id, &mut stream, length) {
+            Ok(Some(extra_field)) => extra_field,
+            _ => Self::Unknown { id, data },
+        }
+    }
+    fn read_field(id: u16, stream: &mut Stream, length: u64) -> Result<Option<Self>> {
+        let mut length = length;
+        Ok(Some(match id {
+            0x0001 => {
+                let mut values = Vec::with_capacity(3);
+                while length >= 8 && values.len() < 3 {
+                    length -= 8;
+                    values.push(stream.read_value::<u64>()?);
+                }
+                let disk = if length >= 4 {
                     Some(stream.read_value()?)
                 } else {
                     None
                 };
+                let mut values = values.into_iter();
+                Self::Zip64 {
+                    uncompressed_size: values.next(),
+                    compressed_size: values.next(),
+                    offset: values.next(),
+                    disk,
+                }
+            }
+            0x5855 => {
+                let mut times = [None; 3];
+                for time in &mut times {
+                    if length >= 4 {
+                        length -= 4;
+                        *time = Some(stream.read_value()?);
+                    }
+                }
+                let [mtime, atime, ctime] = times;
                 Self::UnixExtendedTimestamp {
                     mtime,
                     atime,
@@ -145,44 +455,23 @@ impl ValueRead for Extra {
                 }
             }
             0x5455 => {
-                let mut length: u16 = stream.read_value()?;
+                if length < 1 {
+                    return Ok(None);
+                }
                 length -= 1;
                 let flags: u8 = stream.read_value()?;
-                let mtime = if flags & 0x01 != 0 {
-                    length -= 4;
-                    Some(stream.read_value()?)
-                } else {
-                    None
-                };
-                let atime = if flags & 0x02 != 0 {
-                    if length == 0 {
-                        None
-                    } else {
-                        length -= 4;
-                        Some(stream.read_value()?)
-                    }
-                } else {
-                    None
-                };
-                let ctime = if flags & 0x04 != 0 {
-                    if length == 0 {
-                        None
-                    } else {
+                if flags & 0xF8 != 0 {
+                    return Ok(None);
+                }
+                //中央目录中只有 mtime，atime 和 ctime 按剩余长度读取
+                let mut times = [None; 3];
+                for (bit, time) in times.iter_mut().enumerate() {
+                    if flags & (1 << bit) != 0 && length >= 4 {
                         length -= 4;
-                        Some(stream.read_value()?)
+                        *time = Some(stream.read_value()?);
                     }
-                } else {
-                    None
-                };
-                if length > 0 {
-                    stream.read_value::<u32>()?;
-                }
-                if flags & 0xF8 != 0 {
-                    return Err(Error::new(
-                        ErrorKind::InvalidData,
-                        "Flags is invalid in ExtendedTimestamp",
-                    ));
                 }
+                let [mtime, atime, ctime] = times;
                 Self::UnixExtendedTimestamp {
                     mtime,
                     atime,
@@ -190,66 +479,195 @@ impl ValueRead for Extra {
                 }
             }
             0x7875 => {
-                let _length: u16 = stream.read_value()?;
                 let _version: u8 = stream.read_value()?;
-                let _uid_size: u8 = stream.read_value()?;
+                let uid_size: u8 = stream.read_value()?;
+                if uid_size != 4 {
+                    return Ok(None);
+                }
                 let uid: u32 = stream.read_value()?;
-                let _gid_size: u8 = stream.read_value()?;
+                let gid_size: u8 = stream.read_value()?;
+                if gid_size != 4 {
+                    return Ok(None);
+                }
                 Self::UnixAttrs {
                     uid,
                     gid: stream.read_value()?,
                 }
             }
             0x000A => {
-                let mut _length: u16 = stream.read_value()?;
                 let _reserved: u32 = stream.read_value()?;
-                _length -= 4;
                 let tag: u16 = stream.read_value()?;
-                _length -= 2;
-                if tag != 0x0001 {
-                    return Err(Error::new(
-                        ErrorKind::InvalidData,
-                        "Tag is invalid in NtfsTimestamp",
-                    ));
-                }
                 let size: u16 = stream.read_value()?;
-                _length -= 2;
-                if size != 24 {
-                    return Err(Error::new(
-                        ErrorKind::InvalidData,
-                        "Invalid NTFS Timestamps size",
-                    ));
-                }
-                let mtime: u64 = if _length > 0 {
-                    _length -= 8;
-                    stream.read_value::<u64>()?
-                } else {
-                    0
-                };
-                let atime: u64 = if _length > 0 {
-                    _length -= 8;
-                    stream.read_value::<u64>()?
-                } else {
-                    0
+                if tag != 0x0001 || size != 24 {
+                    return Ok(None);
+                }
+                Self::NTFS {
+                    mtime: stream.read_value()?,
+                    atime: stream.read_value()?,
+                    ctime: stream.read_value()?,
+                }
+            }
+            0x7075 | 0x6375 => {
+                let version: u8 = stream.read_value()?;
+                let crc32: u32 = stream.read_value()?;
+                let value = stream.read_exact_size(length.saturating_sub(5))?;
+                //不是合法的 UTF-8 时忽略该字段，使用头部中的名称
+                let Ok(value) = String::from_utf8(value) else {
+                    return Ok(None);
                 };
-                let ctime: u64 = if _length > 0 {
-                    _length -= 8;
-                    stream.read_value::<u64>()?
+                if id == 0x7075 {
+                    Self::UnicodePath {
+                        version,
+                        crc32,
+                        name: value,
+                    }
                 } else {
-                    0
-                };
-                Self::NTFS {
-                    mtime,
-                    atime,
-                    ctime,
+                    Self::UnicodeComment {
+                        version,
+                        crc32,
+                        comment: value,
+                    }
                 }
             }
-            _ => {
-                return Err(Error::new(
-                    ErrorKind::InvalidData,
-                    format!("Extra id {} not match", id),
-                ));
+            0x9901 => {
+                if length != AES_EXTRA_DATA_SIZE as u64 {
+                    return Ok(None);
+                }
+                let version: u16 = stream.read_value()?;
+                let _vendor_id: u16 = stream.read_value()?;
+                let strength: u8 = stream.read_value()?;
+                let Some(strength) = AesStrength::from_u8(strength) else {
+                    return Ok(None);
+                };
+                Self::Aes {
+                    version,
+                    strength,
+                    compression_method: stream.read_value()?,
+                }
             }
+            _ => return Ok(None),
+        }))
+    }
+}
+//缓存中的扩展字段按 ZIP 中的原始格式保存，Padding 也能原样还原
+#[derive(Debug, Clone)]
+pub struct ExtraFieldsWrapper(pub Vec<Extra>);
+impl ValueRead for ExtraFieldsWrapper {
+    fn read(stream: &mut Stream) -> Result<Self> {
+        let data: Vec<u8> = stream.read_value()?;
+        Ok(ExtraFieldsWrapper(Extra::parse_fields(&data)))
+    }
+}
+impl ValueWrite for ExtraFieldsWrapper {
+    fn write(self, endian: &Endian) -> Result<Stream> {
+        let mut fields = Stream::empty();
+        fields.with_endian(Endian::Little);
+        for extra_field in self.0 {
+            fields.write_value(extra_field)?;
+        }
+        let data: Vec<u8> = fields.take_data()?;
+        let mut stream = Stream::empty();
+        stream.with_endian(endian.clone());
+        stream.write_value(data)?;
+        Ok(stream)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoding::{FileNameEncoding, RawFileName};
+    use crate::zip::Zip;
+
+    //Python 生成，本地头和中央目录都带一个未知的 0xCAFE 字段
+    const UNKNOWN_EXTRA_ARCHIVE: [u8; 138] = [
+        0x50, 0x4b, 0x03, 0x04, 0x14, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x60, 0x21, 0x5a, 0x27,
+        0x70, 0x72, 0x10, 0x0e, 0x00, 0x00, 0x00, 0x0e, 0x00, 0x00, 0x00, 0x05, 0x00, 0x08, 0x00,
+        0x61, 0x2e, 0x74, 0x78, 0x74, 0xfe, 0xca, 0x04, 0x00, 0x62, 0x65, 0x65, 0x66, 0x75, 0x6e,
+        0x6b, 0x6e, 0x6f, 0x77, 0x6e, 0x20, 0x65, 0x78, 0x74, 0x72, 0x61, 0x0a, 0x50, 0x4b, 0x01,
+        0x02, 0x14, 0x00, 0x14, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x60, 0x21, 0x5a, 0x27, 0x70,
+        0x72, 0x10, 0x0e, 0x00, 0x00, 0x00, 0x0e, 0x00, 0x00, 0x00, 0x05, 0x00, 0x08, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x61, 0x2e,
+        0x74, 0x78, 0x74, 0xfe, 0xca, 0x04, 0x00, 0x62, 0x65, 0x65, 0x66, 0x50, 0x4b, 0x05, 0x06,
+        0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x01, 0x00, 0x3b, 0x00, 0x00, 0x00, 0x39, 0x00, 0x00,
+        0x00, 0x00, 0x00,
+    ];
+    const UNKNOWN_FIELD: [u8; 8] = [0xFE, 0xCA, 0x04, 0x00, b'b', b'e', b'e', b'f'];
+
+    fn has_unknown(extra_fields: &[Extra]) -> bool {
+        extra_fields.iter().any(|extra_field| {
+            matches!(extra_field, Extra::Unknown { id: 0xCAFE, data } if data == b"beef")
         })
     }
+
+    #[test]
+    fn keep_unknown_fields() {
+        let mut zip = Zip::new(Stream::new(UNKNOWN_EXTRA_ARCHIVE.to_vec().into())).unwrap();
+        assert!(has_unknown(&zip.directory("a.txt").unwrap().extra_fields));
+        let mut output = Stream::empty();
+        zip.package(&mut output, &mut |_, _, _| {}).unwrap();
+        let data = output.take_data().unwrap();
+        let count = data
+            .windows(UNKNOWN_FIELD.len())
+            .filter(|window| *window == UNKNOWN_FIELD)
+            .count();
+        assert_eq!(count, 2);
+
+        let mut zip = Zip::new(Stream::new(data.into())).unwrap();
+        let directory = zip.directory("a.txt").unwrap();
+        assert!(has_unknown(&directory.extra_fields));
+        let data = directory.decompressed().unwrap().take_data().unwrap();
+        assert_eq!(data, b"unknown extra\n");
+    }
+    #[test]
+    fn keep_malformed_fields() {
+        //长度不足的 Unicode Path 字段保留为 Unknown，不足 4 字节的尾部保留为 Padding
+        let data = [0x75, 0x70, 0x03, 0x00, 1, 2, 3, 0, 0];
+        let extra_fields = Extra::parse_fields(&data);
+        assert!(matches!(
+            &extra_fields[..],
+            [Extra::Unknown { id: 0x7075, data }, Extra::Padding { data: padding }]
+                if data == &[1, 2, 3] && padding == &[0, 0]
+        ));
+    }
+    #[test]
+    fn unicode_path_fields() {
+        //头部是 CP437 的 "Ç.txt"，Unicode Path 的 crc 与之一致时优先使用
+        let header = b"\x80.txt";
+        let mut stream = Stream::empty();
+        stream
+            .write_value(Extra::unicode_path("é.txt", header))
+            .unwrap();
+        stream
+            .write_value(Extra::unicode_comment("注释", b"\x80"))
+            .unwrap();
+        let extra_fields = Extra::parse_fields(&stream.take_data().unwrap());
+        assert_eq!(
+            Extra::find_unicode_path(&extra_fields, header).as_deref(),
+            Some("é.txt")
+        );
+        assert_eq!(Extra::find_unicode_path(&extra_fields, b"a.txt"), None);
+        assert_eq!(
+            Extra::find_unicode_comment(&extra_fields, b"\x80").as_deref(),
+            Some("注释")
+        );
+        let (file_name, raw_file_name) =
+            RawFileName::decode(&extra_fields, 0, header.to_vec(), &FileNameEncoding::Auto);
+        assert_eq!(file_name, "é.txt");
+        assert_eq!(raw_file_name.unwrap().bytes, header);
+    }
+    #[test]
+    fn update_unicode_path() {
+        let header = b"\x80.txt";
+        let mut extra_fields = vec![Extra::unicode_path("old.txt", header)];
+        //旧编码的头部只保留与名称一致的字段
+        Extra::update_unicode_path(&mut extra_fields, "é.txt", header, 0);
+        assert!(matches!(
+            &extra_fields[..],
+            [Extra::UnicodePath { name, .. }] if name == "é.txt"
+        ));
+        //UTF-8 的头部不需要 Unicode Path
+        Extra::update_unicode_path(&mut extra_fields, "é.txt", "é.txt".as_bytes(), UTF8_FLAG);
+        assert!(extra_fields.is_empty());
+    }
 }
@@ -5,4 +5,12 @@ pub mod magic;
 pub mod zip;
 pub mod cache;
 pub mod extra;
-pub mod zip_file;
\ No newline at end of file
+pub mod zip_file;
+pub mod entry_info;
+pub mod encoding;
+pub mod crc32;
+pub mod datetime;
+pub mod attributes;
+pub mod adaptive;
+pub mod codec;
+pub mod crypto;
\ No newline at end of file
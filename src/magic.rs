@@ -1,5 +1,9 @@
+use crate::error::ZipError;
+use fast_stream::bytes::Bytes;
 use fast_stream::derive::NumToEnum;
 use fast_stream::enum_to_bytes;
+use fast_stream::stream::Stream;
+use std::io::{Result, Seek};
 
 #[repr(u32)]
 #[derive(Debug, Clone, PartialEq, NumToEnum)]
@@ -7,11 +11,29 @@ pub enum Magic {
     EoCd = 0x06054b50,
     Directory = 0x02014b50,
     File = 0x04034b50,
+    Zip64EoCd = 0x06064b50,
+    Zip64EoCdLocator = 0x07064b50,
+    DataDescriptor = 0x08074b50,
     // Unknown(u32),
 }
 impl Magic {
     pub const fn byte_size() -> usize {
         4
     }
+    //读取并校验签名，不匹配时返回带偏移的 BadSignature
+    pub fn expect(self, stream: &mut Stream) -> Result<()> {
+        let offset = stream.stream_position()?;
+        let expected: u32 = self.into();
+        let found: u32 = stream.read_value()?;
+        if found != expected {
+            return Err(ZipError::BadSignature {
+                expected,
+                found,
+                offset,
+            }
+            .into());
+        }
+        Ok(())
+    }
 }
 enum_to_bytes!(Magic, u32);
@@ -1,4 +1,15 @@
-use crate::directory::{CompressionMethod, Directory};
+use crate::adaptive::Adaptive;
+use crate::attributes::{
+    external_attributes, DEFAULT_DIR_MODE, DEFAULT_FILE_MODE, DEFAULT_SYMLINK_MODE, DOS_DIRECTORY,
+    OS_UNIX,
+};
+use crate::codec;
+use crate::crc32::crc32;
+use crate::crypto::AesStrength;
+use crate::datetime;
+use crate::directory::{CompressionMethod, DIRECTORY_HEADER_SIZE, Directory, u16_length};
+use crate::encoding::FileNameEncoding;
+use crate::entry_info::Entries;
 use crate::eocd::EoCd;
 use crate::error::ZipError;
 use crate::extra::Extra;
@@ -6,11 +17,14 @@ use crate::zip_file::ZipFile;
 use fast_stream::bytes::{Bytes, ValueRead, ValueWrite};
 use fast_stream::deflate::CompressionLevel;
 use fast_stream::endian::Endian;
-use fast_stream::pin::Pin;
 use fast_stream::stream::Stream;
 use indexmap::IndexMap;
 use std::cmp::min;
-use std::io::Read;
+use std::fs;
+use std::io::{Read, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
 
 #[derive(Debug, Clone)]
 pub struct Parser;
@@ -22,9 +36,14 @@ pub struct Zip<TYPE> {
     pub(crate) stream_size: u64,
     pub(crate) stream: Option<Stream>,
     pub(crate) crc32_computer: bool,
+    pub(crate) zip64: bool,
+    pub(crate) file_name_encoding: FileNameEncoding,
     pub(crate) eo_cd: Option<EoCd<TYPE>>,
     pub write_clear: bool,
     pub(crate) compression_level: CompressionLevel,
+    pub(crate) adaptive: Option<Adaptive>,
+    //解压时是否校验，新增和解析的条目都使用该设置
+    pub(crate) verify: bool,
     pub directories: IndexMap<String, Directory<TYPE>>,
 }
 #[derive(Debug, Clone)]
@@ -69,6 +88,57 @@ impl ValueWrite for CompressionLevelWrapper {
         Ok(stream)
     }
 }
+//单个条目的选项，compression_level 为 None 时使用 Zip 的设置，modified 为 None 时使用当前时间
+//compression_method 为 None 时使用 Deflate，并允许自适应改为 Store
+#[derive(Debug, Clone, Default)]
+pub struct FileOptions {
+    pub compression_method: Option<CompressionMethod>,
+    pub compression_level: Option<CompressionLevel>,
+    pub password: Option<Vec<u8>>,
+    pub aes_strength: Option<AesStrength>,
+    pub modified: Option<SystemTime>,
+    pub zstd_level: Option<i32>,
+}
+impl FileOptions {
+    pub fn with_compression_method(mut self, compression_method: CompressionMethod) -> Self {
+        self.compression_method = Some(compression_method);
+        self
+    }
+    pub fn with_compression_level(mut self, compression_level: CompressionLevel) -> Self {
+        self.compression_level = Some(compression_level);
+        self
+    }
+    pub fn with_aes_encryption(mut self, password: &[u8], strength: AesStrength) -> Self {
+        self.password = Some(password.to_vec());
+        self.aes_strength = Some(strength);
+        self
+    }
+    pub fn with_modified(mut self, modified: SystemTime) -> Self {
+        self.modified = Some(modified);
+        self
+    }
+    pub fn with_modified_unix(self, seconds: i64) -> Self {
+        self.with_modified(datetime::system_time(seconds, 0))
+    }
+    //Zstd 使用的级别，可以是 zstd::compression_level_range() 内的任意值
+    pub fn with_zstd_level(mut self, zstd_level: i32) -> Self {
+        self.zstd_level = Some(zstd_level);
+        self
+    }
+}
+//Zip::test 中单个条目的检查结果
+#[derive(Debug)]
+pub struct EntryTest {
+    pub name: String,
+    pub compressed_size: u64,
+    pub uncompressed_size: u64,
+    pub result: Result<(), ZipError>,
+}
+impl EntryTest {
+    pub fn is_ok(&self) -> bool {
+        self.result.is_ok()
+    }
+}
 impl Zip<Parser> {
     pub fn size(&self) -> u64 {
         self.stream_size
@@ -76,11 +146,47 @@ impl Zip<Parser> {
     pub fn with_crc32(&mut self, value: bool) {
         self.crc32_computer = value;
     }
+    //没有 UTF-8 标记的文件名使用的编码，需要在 parse 之前设置
+    pub fn with_file_name_encoding(&mut self, encoding: FileNameEncoding) {
+        self.file_name_encoding = encoding;
+    }
+    //Deflate 条目按扩展名和内容自动选择是否改用 Store
+    pub fn with_adaptive(&mut self, value: bool) {
+        self.adaptive = if value { Some(Adaptive::default()) } else { None };
+    }
+    //替换自适应模式跳过压缩的扩展名列表(不含点)，同时开启自适应模式
+    pub fn with_store_extensions(&mut self, extensions: &[&str]) {
+        self.adaptive = Some(Adaptive {
+            store_extensions: extensions
+                .iter()
+                .map(|extension| extension.trim_start_matches('.').to_string())
+                .collect(),
+        });
+    }
+    //解压时是否校验 crc32 和大小，默认开启
+    pub fn with_verify(&mut self, value: bool) {
+        self.verify = value;
+        for (_, director) in &mut self.directories {
+            director.with_verify(value);
+        }
+    }
+    //已解析条目解密时使用的密码
+    pub fn with_password(&mut self, password: &[u8]) {
+        for (_, director) in &mut self.directories {
+            director.with_password(password);
+        }
+    }
+    //强制所有条目都写 ZIP64 字段
+    pub fn with_zip64(&mut self, value: bool) {
+        self.zip64 = value;
+    }
     pub fn create(stream: Stream) -> Self {
         Zip {
             stream_size: stream.length(),
             stream: Some(stream),
             crc32_computer: false,
+            zip64: false,
+            file_name_encoding: FileNameEncoding::default(),
             eo_cd: Some(EoCd {
                 r#type: Parser,
                 number_of_disk: 0,
@@ -89,10 +195,13 @@ impl Zip<Parser> {
                 entries: 0,
                 size: 0,
                 offset: 0,
-                comment_length: 0,
+                comment: vec![],
+                zip64: false,
             }),
             write_clear: true,
             compression_level: CompressionLevel::DefaultLevel,
+            adaptive: None,
+            verify: true,
             directories: Default::default(),
         }
     }
@@ -103,24 +212,59 @@ impl Zip<Parser> {
             eo_cd: None,
             write_clear: true,
             crc32_computer: true,
+            zip64: false,
+            file_name_encoding: FileNameEncoding::default(),
             compression_level: CompressionLevel::DefaultLevel,
+            adaptive: None,
+            verify: true,
             directories: IndexMap::new(),
         };
         info.parse()?;
         Ok(info)
     }
+    pub fn comment(&self) -> &[u8] {
+        match &self.eo_cd {
+            Some(eo_cd) => &eo_cd.comment,
+            None => &[],
+        }
+    }
+    //注释长度字段只有 16 位，超出时返回 LimitExceeded
+    pub fn set_comment(&mut self, comment: &[u8]) -> Result<(), ZipError> {
+        u16_length("", "archive comment", comment.len())?;
+        if let Some(eo_cd) = &mut self.eo_cd {
+            eo_cd.comment = comment.to_vec();
+        }
+        Ok(())
+    }
+    //只读取 EOCD 和中央目录，用于快速列出条目
+    pub fn entries(
+        stream: &mut Stream,
+        encoding: FileNameEncoding,
+    ) -> Result<Entries<'_>, ZipError> {
+        Ok(Entries::new(stream, encoding)?)
+    }
     pub fn with_compression_level(&mut self, compression_level: CompressionLevel) {
         self.compression_level = compression_level
     }
     pub fn parse(&mut self) -> Result<(), ZipError> {
         if let Some(mut stream) = std::mem::take(&mut self.stream) {
             let eo_cd = stream.read_value::<EoCd<Parser>>()?;
-            stream.set_position(eo_cd.offset as u64)?;
-            let mut directories = IndexMap::with_capacity(eo_cd.entries as usize);
+            stream.set_position(eo_cd.offset)?;
+            //entries 来自不可信的 EOCD，按中央目录大小能容纳的记录数限制预分配
+            let capacity = min(eo_cd.entries, eo_cd.size / DIRECTORY_HEADER_SIZE as u64);
+            let mut directories = IndexMap::with_capacity(capacity as usize);
             for _ in 0..eo_cd.entries {
-                let dir: Directory<Parser> = stream.read_value()?;
+                let offset = stream.stream_position()?;
+                let mut dir = Directory::read_with(&mut stream, &self.file_name_encoding)
+                    .map_err(|e| ZipError::map_eof(e, offset))?;
+                dir.with_verify(self.verify);
                 directories.insert(dir.file_name.clone(), dir);
             }
+            //条目只保留对源数据的引用，用到时才读取
+            let source = Arc::new(Mutex::new(stream));
+            for (_, dir) in &mut directories {
+                dir.source = Some(source.clone());
+            }
             self.directories = directories;
             self.eo_cd = Some(eo_cd);
         }
@@ -129,19 +273,33 @@ impl Zip<Parser> {
 
     #[allow(dead_code)]
     pub fn add_folder(&mut self, file_name: &str) -> Result<(), ZipError> {
-        let file_name_length = file_name.as_bytes().len() as u16;
+        self.add_folder_with_options(file_name, FileOptions::default())
+    }
+    //目录只使用 options 中的 modified
+    pub fn add_folder_with_options(
+        &mut self,
+        file_name: &str,
+        options: FileOptions,
+    ) -> Result<(), ZipError> {
+        let file_name_length = file_name.len() as u16;
+        let modified = options.modified.unwrap_or_else(SystemTime::now);
+        let (seconds, _) = datetime::unix_time(modified);
+        let (last_modification_time, last_modification_date) = datetime::to_dos(seconds);
+        let timestamp = seconds.clamp(i32::MIN as i64, i32::MAX as i64) as i32;
         let directory = Directory {
             r#type: Parser,
             compressed: true,
             data: Stream::empty(),
+            source: None,
             created_zip_spec: 0x1E, //3.0
-            created_os: 3,          //Uninx
+            created_os: OS_UNIX,
             extract_zip_spec: 14,   //2.0
             extract_os: 0,          //MS-DOS
             flags: 0,
             compression_method: CompressionMethod::Store,
-            last_modification_time: 39620,
-            last_modification_date: 23170,
+            compression_level: None,
+            last_modification_time,
+            last_modification_date,
             crc_32_uncompressed_data: 0,
             compressed_size: 0,
             uncompressed_size: 0,
@@ -150,12 +308,13 @@ impl Zip<Parser> {
             file_comment_length: 0,
             number_of_starts: 0,
             internal_file_attributes: 0,
-            external_file_attributes: 0x41ED0010,
+            external_file_attributes: external_attributes(DEFAULT_DIR_MODE, DOS_DIRECTORY),
             offset_of_local_file_header: 0,
             file_name: file_name.to_string(),
+            raw_file_name: None,
             extra_fields: vec![
                 Extra::UnixExtendedTimestamp {
-                    mtime: Some(0),
+                    mtime: Some(timestamp),
                     atime: None,
                     ctime: None,
                 },
@@ -171,18 +330,19 @@ impl Zip<Parser> {
                 extract_os: 0,          //MS-DOS
                 flags: 0,
                 compression_method: CompressionMethod::Store,
-                last_modification_time: 39620,
-                last_modification_date: 23170,
+                last_modification_time,
+                last_modification_date,
                 crc_32_uncompressed_data: 0,
                 compressed_size: 0,
                 uncompressed_size: 0,
                 file_name_length,
                 extra_field_length: 0,
                 file_name: file_name.to_string(),
+                raw_file_name: None,
                 extra_fields: vec![
                     Extra::UnixExtendedTimestamp {
-                        mtime: Some(0),
-                        atime: Some(0),
+                        mtime: Some(timestamp),
+                        atime: Some(timestamp),
                         ctime: None,
                     },
                     Extra::UnixAttrs { uid: 503, gid: 20 },
@@ -190,6 +350,11 @@ impl Zip<Parser> {
                 data_descriptor: None,
                 data_position: 0,
             },
+            password: None,
+            aes_strength: None,
+            zstd_level: None,
+            verify: self.verify,
+            adaptive: false,
         };
         // let mut extra_field_length = 0;
         // for extra_field in &directory.extra_fields {
@@ -205,6 +370,25 @@ impl Zip<Parser> {
             .insert(directory.file_name.clone(), directory);
         Ok(())
     }
+    pub fn directory(&mut self, file_name: &str) -> Result<&mut Directory<Parser>, ZipError> {
+        self.directories
+            .get_mut(file_name)
+            .ok_or_else(|| ZipError::EntryNotFound {
+                name: file_name.to_string(),
+            })
+    }
+    //检查整个归档，一个条目出错不影响其它条目的检查，加密条目需要先设置密码
+    pub fn test(&mut self) -> Vec<EntryTest> {
+        self.directories
+            .values_mut()
+            .map(|directory| EntryTest {
+                name: directory.file_name.clone(),
+                compressed_size: directory.compressed_size,
+                uncompressed_size: directory.uncompressed_size,
+                result: directory.test(),
+            })
+            .collect()
+    }
     pub fn remove_file(&mut self, file_name: &str) {
         self.directories.swap_remove(file_name);
     }
@@ -253,8 +437,22 @@ impl Zip<Parser> {
         let ratio = non_text_count as f32 / data.len() as f32;
         ratio > bin_threshold
     }
-    pub fn add_file(&mut self, mut data: Stream, file_name: &str) -> Result<(), ZipError> {
-        let uncompressed_size = data.length() as u32;
+    pub fn add_file(&mut self, data: Stream, file_name: &str) -> Result<(), ZipError> {
+        self.add_file_with_options(data, file_name, FileOptions::default())
+    }
+    pub fn add_file_with_options(
+        &mut self,
+        mut data: Stream,
+        file_name: &str,
+        options: FileOptions,
+    ) -> Result<(), ZipError> {
+        if let Some(compression_method) = &options.compression_method {
+            codec::check_compress(file_name, compression_method)?;
+        }
+        if let Some(zstd_level) = options.zstd_level {
+            codec::check_zstd_level(file_name, zstd_level)?;
+        }
+        let uncompressed_size = data.length();
         let crc_32_uncompressed_data = 0; //data.crc32_value();
         let compressed_size = uncompressed_size; //data.compress(CompressionLevel::DefaultLevel)? as u32;
 
@@ -263,18 +461,28 @@ impl Zip<Parser> {
         data.seek_start()?;
         let internal_file_attributes = if Self::is_binary(&buffer) { 0 } else { 1 };
 
+        let modified = options.modified.unwrap_or_else(SystemTime::now);
+        let (seconds, _) = datetime::unix_time(modified);
+        let (last_modification_time, last_modification_date) = datetime::to_dos(seconds);
+        let timestamp = seconds.clamp(i32::MIN as i64, i32::MAX as i64) as i32;
+        let adaptive = options.compression_method.is_none();
+        let compression_method = options
+            .compression_method
+            .unwrap_or(CompressionMethod::Deflate);
         let directory = Directory {
             r#type: Parser,
             compressed: false,
             data,
+            source: None,
             created_zip_spec: 0x1E, //3.0
-            created_os: 0x03,       //Uninx
+            created_os: OS_UNIX,
             extract_zip_spec: 0x0E, //2.0
             extract_os: 0,          //MS-DOS
             flags: 0,
-            compression_method: CompressionMethod::Deflate,
-            last_modification_time: 39620,
-            last_modification_date: 23170,
+            compression_method: compression_method.clone(),
+            compression_level: options.compression_level,
+            last_modification_time,
+            last_modification_date,
             crc_32_uncompressed_data,
             compressed_size,
             uncompressed_size,
@@ -283,12 +491,13 @@ impl Zip<Parser> {
             file_comment_length: 0,
             number_of_starts: 0,
             internal_file_attributes,
-            external_file_attributes: 2175008768,
+            external_file_attributes: external_attributes(DEFAULT_FILE_MODE, 0),
             offset_of_local_file_header: 0,
             file_name: file_name.to_string(),
+            raw_file_name: None,
             extra_fields: vec![
                 Extra::UnixExtendedTimestamp {
-                    mtime: Some(1736154637),
+                    mtime: Some(timestamp),
                     atime: None,
                     ctime: None,
                 },
@@ -300,19 +509,20 @@ impl Zip<Parser> {
                 extract_zip_spec: 0x0E, //1.4
                 extract_os: 0,          //MS-DOS
                 flags: 0,
-                compression_method: CompressionMethod::Deflate,
-                last_modification_time: 39620,
-                last_modification_date: 23170,
+                compression_method,
+                last_modification_time,
+                last_modification_date,
                 crc_32_uncompressed_data,
                 compressed_size,
                 uncompressed_size,
                 file_name_length: 0,
                 extra_field_length: 0,
                 file_name: file_name.to_string(),
+                raw_file_name: None,
                 extra_fields: vec![
                     Extra::UnixExtendedTimestamp {
-                        mtime: Some(1736154637),
-                        atime: Some(1736195293),
+                        mtime: Some(timestamp),
+                        atime: Some(timestamp),
                         ctime: None,
                     },
                     Extra::UnixAttrs { uid: 503, gid: 20 },
@@ -325,6 +535,11 @@ impl Zip<Parser> {
                 // }),
                 data_position: 0,
             },
+            password: options.password,
+            aes_strength: options.aes_strength,
+            zstd_level: options.zstd_level,
+            verify: self.verify,
+            adaptive,
         };
         // let mut extra_field_length = 0;
         // for extra_field in &directory.extra_fields {
@@ -340,6 +555,161 @@ impl Zip<Parser> {
             .insert(directory.file_name.clone(), directory);
         Ok(())
     }
+    //目标作为数据以 Store 方式保存，外部属性标记为 S_IFLNK
+    pub fn add_symlink(&mut self, file_name: &str, target: &str) -> Result<(), ZipError> {
+        let data = Stream::new(target.as_bytes().to_vec().into());
+        let options = FileOptions::default().with_compression_method(CompressionMethod::Store);
+        self.add_file_with_options(data, file_name, options)?;
+        if let Some(directory) = self.directories.get_mut(file_name) {
+            let crc_32_uncompressed_data = crc32(target.as_bytes());
+            directory.crc_32_uncompressed_data = crc_32_uncompressed_data;
+            directory.file.crc_32_uncompressed_data = crc_32_uncompressed_data;
+            directory.set_unix_mode(DEFAULT_SYMLINK_MODE);
+        }
+        Ok(())
+    }
+    //解压到目录，符号链接最后创建，避免后续条目经由链接写到目录之外
+    pub fn extract(&mut self, path: impl AsRef<Path>) -> Result<(), ZipError> {
+        let root = path.as_ref();
+        fs::create_dir_all(root)?;
+        let mut symlinks = vec![];
+        let mut folders = vec![];
+        for (_, director) in &mut self.directories {
+            let target = Self::extract_path(root, &director.file_name)?;
+            Self::check_ancestors(root, &target, &director.file_name)?;
+            if director.is_symlink() {
+                if let Some(link) = director.symlink_target()? {
+                    Self::check_link(&director.file_name, &link)?;
+                    symlinks.push((target, link));
+                }
+            } else if director.is_dir() {
+                fs::create_dir_all(&target)?;
+                folders.push((target, director.permissions()));
+            } else {
+                if let Some(parent) = target.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                if Self::is_symlink_path(&target) {
+                    fs::remove_file(&target)?;
+                }
+                let mut entry = director.clone_all()?;
+                let data = entry.decompressed()?;
+                let mut file = fs::File::create(&target)?;
+                std::io::copy(data, &mut file)?;
+                Self::set_permissions(&target, director.permissions())?;
+            }
+        }
+        //目录权限最后设置，只读目录也能先写入其中的文件
+        for (target, permissions) in folders {
+            Self::set_permissions(&target, permissions)?;
+        }
+        for (target, link) in &symlinks {
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            if Self::is_symlink_path(target) || target.is_file() {
+                fs::remove_file(target)?;
+            }
+            #[cfg(unix)]
+            std::os::unix::fs::symlink(link, target)?;
+            //不支持符号链接的平台按 Info-ZIP 的做法写成内容为目标路径的文件
+            #[cfg(not(unix))]
+            fs::write(target, link.as_bytes())?;
+        }
+        //链接之间可以互相指向，全部创建后再确认实际指向仍在解压目录内
+        #[cfg(unix)]
+        {
+            let root = fs::canonicalize(root)?;
+            for (target, link) in &symlinks {
+                if let Ok(resolved) = fs::canonicalize(target)
+                    && !resolved.starts_with(&root)
+                {
+                    fs::remove_file(target)?;
+                    return Err(Self::unsafe_path(link));
+                }
+            }
+        }
+        Ok(())
+    }
+    fn is_symlink_path(path: &Path) -> bool {
+        fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_symlink())
+    }
+    //解压目录中已有的符号链接会把条目带到目录之外
+    fn check_ancestors(root: &Path, target: &Path, file_name: &str) -> Result<(), ZipError> {
+        for ancestor in target.ancestors().skip(1) {
+            if ancestor == root {
+                break;
+            }
+            if Self::is_symlink_path(ancestor) {
+                return Err(Self::unsafe_path(file_name));
+            }
+        }
+        Ok(())
+    }
+    //链接目标不能是绝对路径，.. 不能越过解压目录
+    fn check_link(file_name: &str, link: &str) -> Result<(), ZipError> {
+        if link.starts_with(['/', '\\']) {
+            return Err(Self::unsafe_path(link));
+        }
+        let mut depth = file_name
+            .split(['/', '\\'])
+            .filter(|component| !component.is_empty() && *component != ".")
+            .count() as i64
+            - 1;
+        for component in link.split(['/', '\\']) {
+            match component {
+                "" | "." => {}
+                ".." => {
+                    depth -= 1;
+                    if depth < 0 {
+                        return Err(Self::unsafe_path(link));
+                    }
+                }
+                #[cfg(windows)]
+                component if component.contains(':') => return Err(Self::unsafe_path(link)),
+                _ => depth += 1,
+            }
+        }
+        Ok(())
+    }
+    #[cfg(unix)]
+    fn set_permissions(path: &Path, permissions: Option<u32>) -> std::io::Result<()> {
+        use crate::attributes::EXTRACT_PERMISSIONS_MASK;
+        use std::os::unix::fs::PermissionsExt;
+        match permissions {
+            Some(permissions) => fs::set_permissions(
+                path,
+                fs::Permissions::from_mode(permissions & EXTRACT_PERMISSIONS_MASK),
+            ),
+            None => Ok(()),
+        }
+    }
+    #[cfg(not(unix))]
+    fn set_permissions(_path: &Path, _permissions: Option<u32>) -> std::io::Result<()> {
+        Ok(())
+    }
+    //拒绝绝对路径、盘符和 ..，防止写到解压目录之外
+    fn extract_path(root: &Path, file_name: &str) -> Result<PathBuf, ZipError> {
+        let mut path = root.to_path_buf();
+        for component in file_name.split(['/', '\\']) {
+            match component {
+                "" | "." => {}
+                ".." => return Err(Self::unsafe_path(file_name)),
+                #[cfg(windows)]
+                component if component.contains(':') => return Err(Self::unsafe_path(file_name)),
+                component => path.push(component),
+            }
+        }
+        if path == root {
+            return Err(Self::unsafe_path(file_name));
+        }
+        Ok(path)
+    }
+    fn unsafe_path(file_name: &str) -> ZipError {
+        ZipError::UnsafePath {
+            name: file_name.to_string(),
+        }
+    }
     fn computer_un_compress_size(&mut self) -> usize {
         let mut total_size = 0;
         for (_, director) in &mut self.directories {
@@ -348,23 +718,31 @@ impl Zip<Parser> {
         total_size
     }
     fn computer(&mut self, callback: &mut impl FnMut(usize)) -> Result<bool, ZipError> {
-        let mut files_size = 0;
-        let mut directors_size = 0;
+        let mut files_size: u64 = 0;
+        let mut directors_size: u64 = 0;
         // self.directories
         //     .sort_keys();
         for (_, director) in &mut self.directories {
-            director.offset_of_local_file_header = files_size as u32;
-            director.exec(self.crc32_computer, &self.compression_level, callback)?;
-            director.file_name_length = director.file_name.len() as u16;
-            director.file.file_name_length = director.file_name_length;
-            files_size += director.file.size() + director.compressed_size as usize;
-            directors_size += director.size();
+            director.set_offset(files_size)?;
+            director.exec(
+                self.crc32_computer,
+                &self.compression_level,
+                self.adaptive.as_ref(),
+                callback,
+            )?;
+            director.update_file_name();
+            director.update_zip64(self.zip64);
+            director.update_version_needed();
+            director.update_data_descriptor();
+            files_size += director.file.size() as u64 + director.compressed_size;
+            directors_size += director.size() as u64;
         }
         if let Some(eo_cd) = &mut self.eo_cd {
-            eo_cd.size = directors_size as u32;
-            eo_cd.entries = self.directories.len() as u16;
+            eo_cd.size = directors_size;
+            eo_cd.entries = self.directories.len() as u64;
             eo_cd.number_of_directory_disk = eo_cd.entries;
-            eo_cd.offset = files_size as u32;
+            eo_cd.offset = files_size;
+            eo_cd.zip64 = self.zip64 || eo_cd.need_zip64();
         }
         Ok(false)
     }
@@ -399,12 +777,16 @@ impl Zip<Parser> {
             for (_, mut director) in std::mem::take(&mut self.directories) {
                 let mut file = director.file.clone();
                 let mut data_descriptor = file.data_descriptor.take();
-                let mut data = &mut director.data;
                 let mut stream = file.write(&endian)?;
                 stream.seek_start()?;
                 output.append(&mut stream)?;
-                data.seek_start()?;
-                output.append(&mut data)?;
+                if director.is_loaded() {
+                    let data = &mut director.data;
+                    data.seek_start()?;
+                    output.append(data)?;
+                } else {
+                    director.copy_source(output)?;
+                }
                 if let Some(data_descriptor) = data_descriptor.take() {
                     output.write_value(data_descriptor)?;
                 }
@@ -416,13 +798,17 @@ impl Zip<Parser> {
             for (_, director) in &mut self.directories {
                 let mut file = director.file.clone();
                 let mut data_descriptor = file.data_descriptor.take();
-                let mut data = &mut director.data;
                 let mut stream = file.write(&endian)?;
                 director.file.data_descriptor = data_descriptor.clone();
                 stream.seek_start()?;
                 output.append(&mut stream)?;
-                data.seek_start()?;
-                output.append(&mut data)?;
+                if director.is_loaded() {
+                    let data = &mut director.data;
+                    data.seek_start()?;
+                    output.append(data)?;
+                } else {
+                    director.copy_source(output)?;
+                }
                 if let Some(data_descriptor) = data_descriptor.take() {
                     output.write_value(data_descriptor)?;
                 }
@@ -436,11 +822,7 @@ impl Zip<Parser> {
         let mut eo_cd = if self.write_clear {
             self.eo_cd.take()
         } else {
-            if let Some(eo_cd) = &self.eo_cd {
-                Some(eo_cd.clone())
-            } else {
-                None
-            }
+            self.eo_cd.clone()
         };
         if let Some(eo_cd) = eo_cd.take() {
             let mut data = eo_cd.write(&endian)?;
@@ -449,4 +831,395 @@ impl Zip<Parser> {
         }
         Ok(())
     }
+    //不需要 Seek 的输出(HTTP 响应、stdout)，每个条目使用 DataDescriptor，最后写中央目录
+    pub fn package_stream<W: Write>(
+        &mut self,
+        output: &mut W,
+        callback: &mut impl FnMut(usize, usize, String),
+    ) -> Result<(), ZipError> {
+        let endian = Endian::Little;
+        let total_size = self.computer_un_compress_size();
+        let mut binding = 0;
+        let mut callback = Self::create_adapter(total_size, &mut binding, callback);
+        let mut files_size: u64 = 0;
+        //只保留中央目录记录的头部信息，不含条目数据
+        let mut records = Vec::with_capacity(self.directories.len());
+        let mut directories = std::mem::take(&mut self.directories);
+        for (_, director) in &mut directories {
+            director.set_offset(files_size)?;
+            let (written, record) = director.write_stream(
+                output,
+                self.crc32_computer,
+                &self.compression_level,
+                self.adaptive.as_ref(),
+                self.zip64,
+                &mut callback,
+            )?;
+            files_size += written;
+            if self.write_clear {
+                director.data = Stream::empty();
+            }
+            records.push(record);
+        }
+        let entries = directories.len() as u64;
+        if !self.write_clear {
+            self.directories = directories;
+        }
+        let mut directors_size = 0;
+        for record in records {
+            let data = record.write(&endian)?.take_data()?;
+            directors_size += data.len() as u64;
+            output.write_all(&data)?;
+        }
+        let eo_cd = if self.write_clear {
+            self.eo_cd.take()
+        } else {
+            self.eo_cd.clone()
+        };
+        if let Some(mut eo_cd) = eo_cd {
+            eo_cd.size = directors_size;
+            eo_cd.entries = entries;
+            eo_cd.number_of_directory_disk = entries;
+            eo_cd.offset = files_size;
+            eo_cd.zip64 = self.zip64 || eo_cd.need_zip64();
+            output.write_all(&eo_cd.write(&endian)?.take_data()?)?;
+        }
+        output.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::magic::Magic;
+
+    //Python 按 PKWARE 规范生成的 ZipCrypto 条目，没有 bit 3，校验字节是 crc32 的高字节
+    const ZIP_CRYPTO_ARCHIVE: [u8; 136] = [
+        0x50, 0x4b, 0x03, 0x04, 0x14, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x60, 0x21, 0x5a, 0xae,
+        0x96, 0x91, 0xb6, 0x1c, 0x00, 0x00, 0x00, 0x10, 0x00, 0x00, 0x00, 0x05, 0x00, 0x00, 0x00,
+        0x61, 0x2e, 0x74, 0x78, 0x74, 0xde, 0x4f, 0x71, 0x65, 0x68, 0xf8, 0x9b, 0x51, 0xbf, 0x0e,
+        0x9e, 0x62, 0x37, 0x9b, 0xcc, 0x03, 0xe0, 0x0c, 0x20, 0x5c, 0x62, 0xde, 0xd7, 0x28, 0x98,
+        0xd4, 0x24, 0xbd, 0x50, 0x4b, 0x01, 0x02, 0x14, 0x00, 0x14, 0x00, 0x01, 0x00, 0x00, 0x00,
+        0x00, 0x60, 0x21, 0x5a, 0xae, 0x96, 0x91, 0xb6, 0x1c, 0x00, 0x00, 0x00, 0x10, 0x00, 0x00,
+        0x00, 0x05, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x61, 0x2e, 0x74, 0x78, 0x74, 0x50, 0x4b, 0x05, 0x06, 0x00, 0x00,
+        0x00, 0x00, 0x01, 0x00, 0x01, 0x00, 0x33, 0x00, 0x00, 0x00, 0x3f, 0x00, 0x00, 0x00, 0x00,
+        0x00,
+    ];
+
+    fn entry_data(zip: &mut Zip<Parser>, name: &str) -> Vec<u8> {
+        let directory = zip.directory(name).unwrap();
+        directory.decompressed().unwrap().take_data().unwrap()
+    }
+
+    #[test]
+    fn package_stream_keeps_zip_crypto_check() {
+        let mut zip = Zip::new(Stream::new(ZIP_CRYPTO_ARCHIVE.to_vec().into())).unwrap();
+        let mut output = vec![];
+        zip.package_stream(&mut output, &mut |_, _, _| {}).unwrap();
+        let mut zip = Zip::new(Stream::new(output.into())).unwrap();
+        zip.with_password(b"test");
+        assert_eq!(entry_data(&mut zip, "a.txt"), b"hello zipcrypto\n");
+    }
+    #[test]
+    fn test_entries() {
+        let plain = b"test entries\n".repeat(100);
+        let mut zip = Zip::create(Stream::empty());
+        zip.with_crc32(true);
+        zip.add_file(Stream::new(plain.clone().into()), "a.txt")
+            .unwrap();
+        zip.add_file(Stream::new(plain.clone().into()), "b.txt")
+            .unwrap();
+        let mut output = Stream::empty();
+        zip.package(&mut output, &mut |_, _, _| {}).unwrap();
+        let mut data = output.take_data().unwrap();
+        //b.txt 的本地头和中央目录 crc32 同时改写
+        let signature = |magic: Magic| {
+            let magic: u32 = magic.into();
+            let magic = magic.to_le_bytes();
+            data.windows(4).rposition(|window| window == magic).unwrap()
+        };
+        for offset in [
+            signature(Magic::File) + 14,
+            signature(Magic::Directory) + 16,
+        ] {
+            data[offset] ^= 1;
+        }
+
+        let mut zip = Zip::new(Stream::new(data.into())).unwrap();
+        let tests = zip.test();
+        assert_eq!(tests.len(), 2);
+        assert_eq!(tests[0].name, "a.txt");
+        assert_eq!(tests[0].uncompressed_size, plain.len() as u64);
+        assert!(tests[0].compressed_size < tests[0].uncompressed_size);
+        assert!(tests[0].is_ok());
+        assert_eq!(tests[1].name, "b.txt");
+        assert!(matches!(
+            tests[1].result,
+            Err(ZipError::ChecksumMismatch { .. })
+        ));
+        //只计算 crc32，不保留解压结果
+        assert!(!zip.directory("a.txt").unwrap().is_loaded());
+
+        let mut zip = Zip::new(Stream::new(ZIP_CRYPTO_ARCHIVE.to_vec().into())).unwrap();
+        assert!(matches!(
+            zip.test()[0].result,
+            Err(ZipError::Encrypted { .. })
+        ));
+        zip.with_password(b"test");
+        assert!(zip.test()[0].is_ok());
+        zip.with_password(b"wrong");
+        assert!(!zip.test()[0].is_ok());
+    }
+    #[test]
+    fn cache_drops_password() {
+        let mut zip = Zip::new(Stream::new(ZIP_CRYPTO_ARCHIVE.to_vec().into())).unwrap();
+        zip.with_password(b"cache-secret");
+        let bytes = zip.into_cache().unwrap().into_bytes().unwrap();
+        assert!(!bytes.windows(12).any(|window| window == b"cache-secret"));
+
+        let mut zip = Zip::from_cache(bytes).unwrap().into_parser();
+        let directory = zip.directory("a.txt").unwrap();
+        assert!(directory.password.is_none());
+        let result = directory.decompressed().map(|_| ());
+        assert!(matches!(result, Err(ZipError::Encrypted { .. })));
+        zip.with_password(b"test");
+        assert_eq!(entry_data(&mut zip, "a.txt"), b"hello zipcrypto\n");
+    }
+    #[test]
+    fn comment_limit() {
+        let mut zip = Zip::create(Stream::empty());
+        let comment = vec![b'c'; u16::MAX as usize];
+        zip.set_comment(&comment).unwrap();
+        let mut output = Stream::empty();
+        zip.package(&mut output, &mut |_, _, _| {}).unwrap();
+        let zip = Zip::new(output).unwrap();
+        assert_eq!(zip.comment(), comment);
+
+        let mut zip = Zip::create(Stream::empty());
+        let result = zip.set_comment(&vec![b'c'; u16::MAX as usize + 1]);
+        assert!(matches!(result, Err(ZipError::LimitExceeded { .. })));
+        assert!(zip.comment().is_empty());
+    }
+    #[test]
+    fn reject_methods_without_compressor() {
+        let mut zip = Zip::create(Stream::empty());
+        for method in [CompressionMethod::PPMd, CompressionMethod::Deflate64] {
+            let options = FileOptions::default().with_compression_method(method);
+            let result =
+                zip.add_file_with_options(Stream::new(b"a".to_vec().into()), "a.txt", options);
+            assert!(matches!(result, Err(ZipError::UnsupportedMethod { .. })));
+        }
+        assert!(zip.directories.is_empty());
+
+        let options = FileOptions::default().with_compression_method(CompressionMethod::Store);
+        zip.add_file_with_options(Stream::new(b"a".to_vec().into()), "a.txt", options)
+            .unwrap();
+        let directory = zip.directory("a.txt").unwrap();
+        let result = directory.set_compression_method(CompressionMethod::PPMd);
+        assert!(matches!(result, Err(ZipError::UnsupportedMethod { .. })));
+        assert_eq!(directory.compression_method, CompressionMethod::Store);
+        directory
+            .set_compression_method(CompressionMethod::Deflate)
+            .unwrap();
+    }
+    #[test]
+    fn adaptive_store() {
+        //小于抽样下限的随机数据只能在压缩后比较大小时退回 Store
+        let mut seed = 1_u32;
+        let random: Vec<u8> = (0..512)
+            .map(|_| {
+                seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+                (seed >> 16) as u8
+            })
+            .collect();
+        let text = b"adaptive store\n".repeat(100);
+        let mut zip = Zip::create(Stream::empty());
+        zip.with_crc32(true);
+        zip.with_adaptive(true);
+        zip.add_file(Stream::new(random.clone().into()), "random.bin")
+            .unwrap();
+        zip.add_file(Stream::new(text.clone().into()), "a.txt")
+            .unwrap();
+        zip.add_file(Stream::new(text.clone().into()), "a.png")
+            .unwrap();
+        let mut output = Stream::empty();
+        zip.package(&mut output, &mut |_, _, _| {}).unwrap();
+
+        let mut zip = Zip::new(output).unwrap();
+        for (name, method, data) in [
+            ("random.bin", CompressionMethod::Store, &random),
+            ("a.txt", CompressionMethod::Deflate, &text),
+            ("a.png", CompressionMethod::Store, &text),
+        ] {
+            assert_eq!(zip.directory(name).unwrap().compression_method, method);
+            assert_eq!(&entry_data(&mut zip, name), data);
+        }
+    }
+    #[test]
+    fn write_zip64() {
+        let plain = b"zip64 entry\n".to_vec();
+        let create = || {
+            let mut zip = Zip::create(Stream::empty());
+            zip.with_crc32(true);
+            zip.with_zip64(true);
+            zip.add_file(Stream::new(plain.clone().into()), "a.txt")
+                .unwrap();
+            zip
+        };
+        let mut output = Stream::empty();
+        create().package(&mut output, &mut |_, _, _| {}).unwrap();
+        let mut stream_output = vec![];
+        create()
+            .package_stream(&mut stream_output, &mut |_, _, _| {})
+            .unwrap();
+
+        for data in [output.take_data().unwrap(), stream_output] {
+            //EOCD 前是 ZIP64 locator，中央目录的 32 位字段都由扩展字段给出
+            let eocd = data.len() - 22;
+            let locator: u32 = Magic::Zip64EoCdLocator.into();
+            let magic = u32::from_le_bytes(data[eocd - 20..eocd - 16].try_into().unwrap());
+            assert_eq!(magic, locator);
+            let central = u32::from_le_bytes(data[eocd + 16..eocd + 20].try_into().unwrap());
+            let central = central as usize;
+            assert_eq!(data[central + 20..central + 28], [0xFF; 8]);
+            assert_eq!(data[central + 42..central + 46], [0xFF; 4]);
+
+            let mut zip = Zip::new(Stream::new(data.into())).unwrap();
+            assert!(zip.eo_cd.as_ref().unwrap().zip64);
+            let directory = zip.directory("a.txt").unwrap();
+            assert!(matches!(
+                directory.extra_fields[0],
+                Extra::Zip64 {
+                    uncompressed_size: Some(12),
+                    compressed_size: Some(_),
+                    offset: Some(0),
+                    ..
+                }
+            ));
+            assert_eq!(entry_data(&mut zip, "a.txt"), plain);
+        }
+    }
+    fn extract_dir(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("fast-zip-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        path
+    }
+    #[test]
+    fn symlink_entry() {
+        let mut zip = Zip::create(Stream::empty());
+        zip.with_crc32(true);
+        zip.add_file(Stream::new(b"target".to_vec().into()), "dir/a.txt")
+            .unwrap();
+        zip.add_symlink("link", "dir/a.txt").unwrap();
+        let mut output = Stream::empty();
+        zip.package(&mut output, &mut |_, _, _| {}).unwrap();
+
+        let mut zip = Zip::new(output).unwrap();
+        let directory = zip.directory("link").unwrap();
+        assert!(directory.is_symlink());
+        assert_eq!(directory.compression_method, CompressionMethod::Store);
+        let target = directory.symlink_target().unwrap();
+        assert_eq!(target.as_deref(), Some("dir/a.txt"));
+        let directory = zip.directory("dir/a.txt").unwrap();
+        assert_eq!(directory.symlink_target().unwrap(), None);
+
+        let root = extract_dir("symlink");
+        zip.extract(&root).unwrap();
+        assert_eq!(fs::read(root.join("link")).unwrap(), b"target");
+        #[cfg(unix)]
+        assert_eq!(
+            fs::read_link(root.join("link")).unwrap(),
+            Path::new("dir/a.txt")
+        );
+        fs::remove_dir_all(&root).unwrap();
+    }
+    #[test]
+    fn extract_rejects_unsafe_paths() {
+        let root = Path::new("out");
+        assert!(Zip::<Parser>::extract_path(root, "../a.txt").is_err());
+        assert!(Zip::<Parser>::extract_path(root, "a/../../a.txt").is_err());
+        assert_eq!(
+            Zip::<Parser>::extract_path(root, "/a/./b.txt").unwrap(),
+            root.join("a").join("b.txt")
+        );
+        assert!(Zip::<Parser>::check_link("a/link", "../b.txt").is_ok());
+        assert!(Zip::<Parser>::check_link("link", "../b.txt").is_err());
+        assert!(Zip::<Parser>::check_link("link", "/etc/passwd").is_err());
+
+        //条目名带 .. 或链接目标越过解压目录时都拒绝
+        let entries = [("../evil.txt", None), ("link", Some("../outside"))];
+        for (index, (name, link)) in entries.into_iter().enumerate() {
+            let mut zip = Zip::create(Stream::empty());
+            let data = Stream::new(b"a".to_vec().into());
+            match link {
+                Some(link) => zip.add_symlink(name, link).unwrap(),
+                None => zip.add_file(data, name).unwrap(),
+            }
+            let root = extract_dir(&format!("unsafe-{index}"));
+            let result = zip.extract(&root);
+            assert!(matches!(result, Err(ZipError::UnsafePath { .. })));
+            assert!(!root.parent().unwrap().join("evil.txt").exists());
+            fs::remove_dir_all(&root).unwrap();
+        }
+    }
+    #[cfg(feature = "lzma")]
+    #[test]
+    fn lzma_entries() {
+        let plain = b"lzma entries\n".repeat(200);
+        let mut zip = Zip::create(Stream::empty());
+        zip.with_crc32(true);
+        for (name, method) in [
+            ("a.lzma.txt", CompressionMethod::LZMA),
+            ("a.xz.txt", CompressionMethod::XZ),
+        ] {
+            let options = FileOptions::default().with_compression_method(method);
+            zip.add_file_with_options(Stream::new(plain.clone().into()), name, options)
+                .unwrap();
+        }
+        let mut output = Stream::empty();
+        zip.package(&mut output, &mut |_, _, _| {}).unwrap();
+
+        let mut zip = Zip::new(output).unwrap();
+        //LZMA 总是写 EOS 标记，bit 1 置位
+        let directory = zip.directory("a.lzma.txt").unwrap();
+        assert_eq!(directory.compression_method, CompressionMethod::LZMA);
+        assert_eq!(directory.flags & codec::LZMA_EOS_FLAG, codec::LZMA_EOS_FLAG);
+        assert_eq!(directory.extract_zip_spec, 63);
+        assert_eq!(entry_data(&mut zip, "a.lzma.txt"), plain);
+        let directory = zip.directory("a.xz.txt").unwrap();
+        assert_eq!(directory.compression_method, CompressionMethod::XZ);
+        assert_eq!(directory.flags & codec::LZMA_EOS_FLAG, 0);
+        assert_eq!(entry_data(&mut zip, "a.xz.txt"), plain);
+        assert!(zip.test().iter().all(|entry| entry.result.is_ok()));
+    }
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn zstd_level_options() {
+        let plain = b"zstd level\n".repeat(200);
+        let mut zip = Zip::create(Stream::empty());
+        zip.with_crc32(true);
+        let options = FileOptions::default().with_compression_method(CompressionMethod::Zstd);
+        let result = zip.add_file_with_options(
+            Stream::new(plain.clone().into()),
+            "a.txt",
+            options.clone().with_zstd_level(100),
+        );
+        assert!(matches!(result, Err(ZipError::InvalidLevel { .. })));
+        zip.add_file_with_options(
+            Stream::new(plain.clone().into()),
+            "a.txt",
+            options.with_zstd_level(-3),
+        )
+        .unwrap();
+        let directory = zip.directory("a.txt").unwrap();
+        assert!(directory.set_zstd_level(-1000000).is_err());
+        directory.set_zstd_level(19).unwrap();
+        let mut output = Stream::empty();
+        zip.package(&mut output, &mut |_, _, _| {}).unwrap();
+
+        let mut zip = Zip::new(output).unwrap();
+        assert_eq!(entry_data(&mut zip, "a.txt"), plain);
+    }
 }
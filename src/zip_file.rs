@@ -1,4 +1,8 @@
-use crate::directory::CompressionMethod;
+use crate::crypto::ENCRYPTED_FLAG;
+use crate::directory::{CompressionMethod, u16_length, zip64_value};
+use crate::encoding::{FileNameEncoding, RawFileName};
+use crate::eocd::ZIP64_ZIP_SPEC;
+use crate::error::ZipError;
 use crate::extra::Extra;
 use crate::magic::Magic;
 use crate::zip::Parser;
@@ -6,7 +10,8 @@ use fast_stream::bytes::{Bytes, ValueRead, ValueWrite};
 use fast_stream::endian::Endian;
 use fast_stream::pin::Pin;
 use fast_stream::stream::Stream;
-use std::io::{Error, ErrorKind, Result, Seek, Write};
+use std::cmp::max;
+use std::io::{Result, Seek, SeekFrom, Write};
 
 const ZIP_FILE_HEADER_SIZE: usize = Magic::byte_size()
     + size_of::<u16>() * 2
@@ -14,37 +19,79 @@ const ZIP_FILE_HEADER_SIZE: usize = Magic::byte_size()
     + size_of::<u16>() * 2
     + size_of::<u32>() * 3
     + size_of::<u16>() * 2;
+const HEADER_FLAGS_MASK: u16 = ENCRYPTED_FLAG | 0x0008;
 #[derive(Debug, Clone)]
 pub struct DataDescriptor {
+    pub signature: bool,
     pub crc32: u32,
-    pub compressed_size: u32,
-    pub uncompressed_size: u32,
+    pub compressed_size: u64,
+    pub uncompressed_size: u64,
+    pub zip64: bool,
 }
 impl DataDescriptor {
-    pub fn size() -> usize {
-        4 * 4
+    pub fn size(&self) -> usize {
+        let signature_size = if self.signature {
+            Magic::byte_size()
+        } else {
+            0
+        };
+        let sizes_size = if self.zip64 {
+            size_of::<u64>() * 2
+        } else {
+            size_of::<u32>() * 2
+        };
+        signature_size + size_of::<u32>() + sizes_size
+    }
+    //签名 0x08074b50 是可选的，zip64 时大小为 8 字节
+    pub fn read_with(stream: &mut Stream, zip64: bool) -> Result<Self> {
+        let descriptor_magic: u32 = Magic::DataDescriptor.into();
+        stream.pin()?;
+        let magic: u32 = stream.read_value()?;
+        stream.un_pin()?;
+        let signature = magic == descriptor_magic;
+        if signature {
+            stream.seek(SeekFrom::Current(4))?;
+        }
+        let crc32: u32 = stream.read_value()?;
+        let (compressed_size, uncompressed_size) = if zip64 {
+            (stream.read_value::<u64>()?, stream.read_value::<u64>()?)
+        } else {
+            (
+                stream.read_value::<u32>()? as u64,
+                stream.read_value::<u32>()? as u64,
+            )
+        };
+        Ok(Self {
+            signature,
+            crc32,
+            compressed_size,
+            uncompressed_size,
+            zip64,
+        })
     }
 }
 impl ValueRead for DataDescriptor {
     fn read(stream: &mut Stream) -> Result<Self> {
-        let _magic: [u8; 4] = stream.read_value()?;
-        Ok(Self {
-            crc32: stream.read_value()?,
-            compressed_size: stream.read_value()?,
-            uncompressed_size: stream.read_value()?,
-        })
+        Self::read_with(stream, false)
     }
 }
 impl ValueWrite for DataDescriptor {
     fn write(self, endian: &Endian) -> Result<Stream> {
         let mut stream = Stream::empty();
         stream.with_endian(endian.clone());
-        let magic = &[0x50, 0x4B, 0x07, 0x08];
-        stream.write(magic)?;
-        stream
-            .write_value(self.crc32)?
-            .write_value(self.compressed_size)?
-            .write_value(self.uncompressed_size)?;
+        if self.signature {
+            stream.write_value(Magic::DataDescriptor)?;
+        }
+        stream.write_value(self.crc32)?;
+        if self.zip64 {
+            stream
+                .write_value(self.compressed_size)?
+                .write_value(self.uncompressed_size)?;
+        } else {
+            stream
+                .write_value(self.compressed_size as u32)?
+                .write_value(self.uncompressed_size as u32)?;
+        }
         Ok(stream)
     }
 }
@@ -58,11 +105,12 @@ pub struct ZipFile<TYPE> {
     pub last_modification_time: u16,
     pub last_modification_date: u16,
     pub crc_32_uncompressed_data: u32,
-    pub compressed_size: u32,
-    pub uncompressed_size: u32,
+    pub compressed_size: u64,
+    pub uncompressed_size: u64,
     pub file_name_length: u16,
     pub extra_field_length: u16,
     pub file_name: String,
+    pub raw_file_name: Option<RawFileName>,
     pub extra_fields: Vec<Extra>,
     pub data_descriptor: Option<DataDescriptor>,
     pub data_position: u64,
@@ -72,55 +120,69 @@ impl ValueWrite for ZipFile<Parser> {
         let mut stream = Stream::empty();
         stream.with_endian(endian.clone());
         stream.write_value(Magic::File)?;
-        let compression_method = if self.uncompressed_size == 0 {
+        let compression_method = if self.uncompressed_size == 0
+            && self.compression_method != CompressionMethod::AES
+        {
             CompressionMethod::Store
         } else {
             self.compression_method
         };
+        let (zip64_uncompressed_size, zip64_compressed_size, _) =
+            Extra::zip64_fields(&self.extra_fields);
         let file_is_dir = self.file_name.ends_with("/");
-        if file_is_dir {
+        if zip64_uncompressed_size || zip64_compressed_size {
+            stream.write_value(ZIP64_ZIP_SPEC as u8)?; //extract_zip_spec
+        } else if file_is_dir {
             stream.write_value(10_u8)?; //extract_zip_spec
         } else {
-            stream.write_value(14_u8)?; //extract_zip_spec
+            stream.write_value(max(14, compression_method.version_needed()))?; //extract_zip_spec
         }
 
         let mut extra_field_stream = Stream::empty();
-        self.extra_field_length = 0;
+        let mut extra_field_length = 0;
         for extra_field in self.extra_fields {
-            self.extra_field_length += extra_field.size();
+            extra_field_length += extra_field.size() as usize;
             extra_field_stream.write_value(extra_field)?;
         }
         extra_field_stream.seek_start()?;
+        self.extra_field_length = u16_length(&self.file_name, "extra field", extra_field_length)?;
 
-        self.file_name_length = self.file_name.as_bytes().len() as u16;
+        let (file_name, flags) =
+            RawFileName::encode(&self.file_name, &self.raw_file_name, self.flags);
+        self.flags = flags;
+        self.file_name_length = u16_length(&self.file_name, "file name", file_name.len())?;
 
         stream.write_value(self.extract_os)?;
         stream.write_value(self.flags)?;
         stream.write_value(compression_method)?;
         stream.write_value(self.last_modification_time)?;
         stream.write_value(self.last_modification_date)?;
-        stream.write_value(self.crc_32_uncompressed_data)?;
-        stream.write_value(self.compressed_size)?;
-        stream.write_value(self.uncompressed_size)?;
+        //流式(bit 3)时 crc 和大小写在数据后面的 DataDescriptor 中
+        if self.flags & 0x0008 != 0 {
+            stream.write_value(0_u32)?;
+            stream.write_value(zip64_value(zip64_compressed_size, 0))?;
+            stream.write_value(zip64_value(zip64_uncompressed_size, 0))?;
+        } else {
+            stream.write_value(self.crc_32_uncompressed_data)?;
+            stream.write_value(zip64_value(zip64_compressed_size, self.compressed_size))?;
+            stream.write_value(zip64_value(zip64_uncompressed_size, self.uncompressed_size))?;
+        }
         stream.write_value(self.file_name_length)?;
         stream.write_value(self.extra_field_length)?;
-        stream.write(self.file_name.as_bytes())?;
+        stream.write_all(&file_name)?;
         stream.append(&mut extra_field_stream)?;
-        if let Some(data_descriptor) = self.data_descriptor {
-            stream.write_value(data_descriptor)?;
-        }
         Ok(stream)
     }
 }
 impl ValueRead for ZipFile<Parser> {
     fn read(stream: &mut Stream) -> Result<Self> {
-        let magic: Magic = stream.read_value()?;
-        if magic != Magic::File {
-            return Err(Error::new(
-                ErrorKind::InvalidData,
-                "Invalid directory file magic number",
-            ));
-        }
+        Self::read_with(stream, &FileNameEncoding::default())
+    }
+}
+impl ZipFile<Parser> {
+    //文件名按指定的编码解码
+    pub fn read_with(stream: &mut Stream, encoding: &FileNameEncoding) -> Result<Self> {
+        Magic::File.expect(stream)?;
         let mut file = ZipFile {
             r#type: Parser,
             extract_zip_spec: stream.read_value()?,
@@ -130,29 +192,35 @@ impl ValueRead for ZipFile<Parser> {
             last_modification_time: stream.read_value()?,
             last_modification_date: stream.read_value()?,
             crc_32_uncompressed_data: stream.read_value()?,
-            compressed_size: stream.read_value()?,
-            uncompressed_size: stream.read_value()?,
+            compressed_size: stream.read_value::<u32>()? as u64,
+            uncompressed_size: stream.read_value::<u32>()? as u64,
             file_name_length: stream.read_value()?,
             extra_field_length: stream.read_value()?,
             file_name: "".to_string(),
+            raw_file_name: None,
             extra_fields: vec![],
             data_descriptor: None,
             data_position: 0,
         };
         let file_name = stream.read_exact_size(file.file_name_length as u64)?;
-        let file_name =
-            String::from_utf8(file_name).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
-        file.file_name = file_name.clone();
-        let mut total_bytes = 0;
-        if file.extra_field_length > 0 {
-            loop {
-                let position = stream.position()?;
-                let extra_field: Extra = stream.read_value()?; //.read_exact_size(file.extra_field_length as u64)?;
-                file.extra_fields.push(extra_field);
-                let size = stream.position()? - position;
-                total_bytes += size;
-                if total_bytes >= file.extra_field_length as u64 {
-                    break;
+        file.extra_fields = Extra::read_fields(stream, file.extra_field_length)?;
+        let (file_name, raw_file_name) =
+            RawFileName::decode(&file.extra_fields, file.flags, file_name, encoding);
+        file.file_name = file_name;
+        file.raw_file_name = raw_file_name;
+        //本地头的 ZIP64 字段总是同时包含两个大小
+        for extra_field in &file.extra_fields {
+            if let Extra::Zip64 {
+                uncompressed_size,
+                compressed_size,
+                ..
+            } = extra_field
+            {
+                if file.uncompressed_size == u32::MAX as u64 {
+                    file.uncompressed_size = uncompressed_size.unwrap_or(file.uncompressed_size);
+                }
+                if file.compressed_size == u32::MAX as u64 {
+                    file.compressed_size = compressed_size.unwrap_or(file.compressed_size);
                 }
             }
         }
@@ -161,16 +229,115 @@ impl ValueRead for ZipFile<Parser> {
     }
 }
 impl ZipFile<Parser> {
+    //与中央目录中的字段比对，加密和流式标志决定数据的读法，两处必须一致
+    //比较原始字节，两边的文件名可能用不同的编码解码
+    pub fn check_file_name(&self, file_name: &str, file_name_bytes: &[u8]) -> Result<()> {
+        let (bytes, _) = RawFileName::encode(&self.file_name, &self.raw_file_name, self.flags);
+        if bytes != file_name_bytes {
+            return Err(ZipError::LocalHeaderMismatch {
+                name: file_name.to_string(),
+                field: "file name",
+            }
+            .into());
+        }
+        Ok(())
+    }
+    pub fn check_central(
+        &self,
+        file_name: &str,
+        compression_method: &CompressionMethod,
+        flags: u16,
+        crc32: u32,
+        compressed_size: u64,
+        uncompressed_size: u64,
+    ) -> Result<()> {
+        let mismatch = |field| ZipError::LocalHeaderMismatch {
+            name: file_name.to_string(),
+            field,
+        };
+        if self.compression_method != *compression_method {
+            return Err(mismatch("compression method").into());
+        }
+        if self.flags & HEADER_FLAGS_MASK != flags & HEADER_FLAGS_MASK {
+            return Err(mismatch("flags").into());
+        }
+        //流式条目本地头中的 crc 和大小为 0
+        if self.flags & 0x0008 == 0 {
+            if self.crc_32_uncompressed_data != crc32 {
+                return Err(mismatch("crc32").into());
+            }
+            if self.compressed_size != compressed_size
+                || self.uncompressed_size != uncompressed_size
+            {
+                return Err(mismatch("size").into());
+            }
+        }
+        Ok(())
+    }
     pub fn size(&self) -> usize {
-        let mut bytes = ZIP_FILE_HEADER_SIZE + self.file_name.as_bytes().len();
+        let (file_name, _) = RawFileName::encode(&self.file_name, &self.raw_file_name, self.flags);
+        let mut bytes = ZIP_FILE_HEADER_SIZE + file_name.len();
         for extra_field in &self.extra_fields {
             bytes += extra_field.size() as usize
         }
-        let data_descriptor_size = if self.data_descriptor.is_some() {
-            DataDescriptor::size()
-        } else {
-            0
+        let data_descriptor_size = match &self.data_descriptor {
+            Some(data_descriptor) => data_descriptor.size(),
+            None => 0,
         };
         bytes + data_descriptor_size
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::zip::Zip;
+
+    //Python 按规范生成的两个 bit 3 条目，a.txt 的 DataDescriptor 带签名，b.txt 不带
+    const DATA_DESCRIPTOR_ARCHIVE: [u8; 260] = [
+        0x50, 0x4b, 0x03, 0x04, 0x14, 0x00, 0x08, 0x00, 0x00, 0x00, 0x00, 0x60, 0x21, 0x5a, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x05, 0x00, 0x00, 0x00,
+        0x61, 0x2e, 0x74, 0x78, 0x74, 0x73, 0x69, 0x67, 0x6e, 0x65, 0x64, 0x20, 0x64, 0x65, 0x73,
+        0x63, 0x72, 0x69, 0x70, 0x74, 0x6f, 0x72, 0x0a, 0x50, 0x4b, 0x07, 0x08, 0x81, 0xb3, 0x0b,
+        0x54, 0x12, 0x00, 0x00, 0x00, 0x12, 0x00, 0x00, 0x00, 0x50, 0x4b, 0x03, 0x04, 0x14, 0x00,
+        0x08, 0x00, 0x00, 0x00, 0x00, 0x60, 0x21, 0x5a, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x05, 0x00, 0x00, 0x00, 0x62, 0x2e, 0x74, 0x78, 0x74, 0x75,
+        0x6e, 0x73, 0x69, 0x67, 0x6e, 0x65, 0x64, 0x20, 0x64, 0x65, 0x73, 0x63, 0x72, 0x69, 0x70,
+        0x74, 0x6f, 0x72, 0x0a, 0xcc, 0xe0, 0x7c, 0x27, 0x14, 0x00, 0x00, 0x00, 0x14, 0x00, 0x00,
+        0x00, 0x50, 0x4b, 0x01, 0x02, 0x14, 0x00, 0x14, 0x00, 0x08, 0x00, 0x00, 0x00, 0x00, 0x60,
+        0x21, 0x5a, 0x81, 0xb3, 0x0b, 0x54, 0x12, 0x00, 0x00, 0x00, 0x12, 0x00, 0x00, 0x00, 0x05,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x61, 0x2e, 0x74, 0x78, 0x74, 0x50, 0x4b, 0x01, 0x02, 0x14, 0x00, 0x14, 0x00,
+        0x08, 0x00, 0x00, 0x00, 0x00, 0x60, 0x21, 0x5a, 0xcc, 0xe0, 0x7c, 0x27, 0x14, 0x00, 0x00,
+        0x00, 0x14, 0x00, 0x00, 0x00, 0x05, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x45, 0x00, 0x00, 0x00, 0x62, 0x2e, 0x74, 0x78, 0x74, 0x50, 0x4b,
+        0x05, 0x06, 0x00, 0x00, 0x00, 0x00, 0x02, 0x00, 0x02, 0x00, 0x66, 0x00, 0x00, 0x00, 0x88,
+        0x00, 0x00, 0x00, 0x00, 0x00,
+    ];
+
+    #[test]
+    fn read_data_descriptor() {
+        let mut stream = Stream::new(DATA_DESCRIPTOR_ARCHIVE.to_vec().into());
+        for (position, signature, size) in [(53, true, 18), (124, false, 20)] {
+            stream.set_position(position).unwrap();
+            let data_descriptor = DataDescriptor::read_with(&mut stream, false).unwrap();
+            assert_eq!(data_descriptor.signature, signature);
+            assert_eq!(data_descriptor.compressed_size, size);
+            assert_eq!(data_descriptor.uncompressed_size, size);
+            let end = position + data_descriptor.size() as u64;
+            assert_eq!(stream.stream_position().unwrap(), end);
+        }
+
+        let mut zip = Zip::new(stream).unwrap();
+        for (name, plain) in [
+            ("a.txt", &b"signed descriptor\n"[..]),
+            ("b.txt", &b"unsigned descriptor\n"[..]),
+        ] {
+            let directory = zip.directory(name).unwrap();
+            assert_eq!(directory.flags & 0x0008, 0x0008);
+            assert!(directory.test().is_ok());
+            let data = directory.decompressed().unwrap().take_data().unwrap();
+            assert_eq!(data, plain);
+        }
+    }
+}